@@ -1,25 +1,44 @@
 extern crate gbs;
 
 use std::env;
-use std::io::{BufReader, Read};
-use std::fs::File;
 
 use gbs::gb_parser;
+use gbs::symbols;
 use gbs::screen;
 use gbs::gb;
+use gbs::gb::boot_rom::BootRom;
 
 #[macro_use]
 extern crate glium;
 
 use glium::DisplayBuild;
+use glium::glutin::{Event, ElementState};
 
 const SCREEN_ZOOM: usize = 4;
 
+// Usage: gb-boot FILE --boot-rom PATH
+//
+// Run FILE from power on through the DMG or CGB boot ROM read from PATH.
 fn main() {
-  let filename = env::args().nth(1)
-    .expect("No GB file specified");
+  let mut filename = None;
+  let mut boot_rom = None;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--boot-rom" => {
+        boot_rom = Some(args.next().expect("No boot ROM file specified"));
+      }
+      _ => filename = Some(arg),
+    }
+  }
+  let filename = filename.expect("No GB file specified");
+  let boot_rom = boot_rom.expect("No boot ROM specified (--boot-rom PATH)");
+  let boot_rom = BootRom::load(&boot_rom).unwrap_or_else(|e| {
+    panic!("Error loading boot ROM {}: {}", boot_rom, e)
+  });
 
-  let gbs = gb_parser::load(filename)
+  let gbs = gb_parser::load(&filename)
     .expect("Error loading GB file");
 
   println!("Title: {}", gbs.title);
@@ -28,13 +47,9 @@ fn main() {
 
   let mut gb = gb::GB::new();
 
-  // Load BIOS and ROM
   gb.load_rom(&gbs.rom, 0);
-
-  let bios = File::open("boot.rom").expect("No BIOS rom found");
-  let bios_file = BufReader::new(bios);
-  let bios : Vec<u8> = bios_file.bytes().filter_map(|b| b.ok()).collect();
-  gb.load_rom(&bios, 0);
+  gb.cpu.symbols = symbols::load_beside(&filename)
+    .expect("Error loading symbol file");
 
   // Init screen
   let display = glium::glutin::WindowBuilder::new()
@@ -46,12 +61,31 @@ fn main() {
 
   let mut lcd = gb::lcd::LCD::new();
 
-  // Reset
-  gb.reset();
+  // Power on into the boot ROM
+  gb.boot(boot_rom);
 
   // Play
+  let mut slot = 1;
+  // Report a lockup once, and keep showing the screen
+  let mut locked = false;
   loop {
-    gb.run_for(70224);
+    match gb.run_for(70224) {
+      Err(lockup) if !locked => {
+        println!("CPU locked up: {}", lockup);
+        locked = true;
+      }
+      Err(_) => {},
+      Ok(()) => locked = false,
+    }
+
+    for event in display.poll_events() {
+      match event {
+        Event::Closed => return,
+        Event::KeyboardInput(ElementState::Pressed, _, Some(key)) =>
+          screen::hotkey(&mut gb, &filename, &mut slot, key),
+        _ => {},
+      }
+    }
 
     let mut frame = display.draw();
 
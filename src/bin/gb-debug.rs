@@ -0,0 +1,292 @@
+extern crate gbs;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use gbs::debugger::{Breakpoint, Cmp, Condition, Debugger, Reg, Stop};
+use gbs::debugger::RUN_LIMIT;
+use gbs::gb::GB_FREQ;
+use gbs::gb::bus::Bus;
+use gbs::gb::hardware::{Access, Hardware, Watchpoint};
+use gbs::gb::model::Model;
+use gbs::gb_parser;
+use gbs::gbs_parser;
+use gbs::symbols::{self, Symbols};
+use gbs::util::{parse_u8, parse_u16};
+
+fn usage() -> ! {
+  println!("Usage: gb-debug FILE [TRACK] [--sym PATH] [--model MODEL]");
+  println!();
+  println!("FILE is a ROM, or a GBS file stopped at the start of INIT for");
+  println!("TRACK (0-based, default 0).  Labels are read from the symbol file");
+  println!("PATH, as written by rgblink, or FILE.sym by default.  A ROM starts");
+  println!("as MODEL's boot ROM leaves it: dmg (default), mgb, sgb or cgb.");
+  std::process::exit(1);
+}
+
+fn help() {
+  println!("Numbers are decimal, or hexadecimal with a 0x or $ prefix.");
+  println!("Addresses can also be labels from the symbol file.");
+  println!("  b ADDR [if REG OP N]  break at ADDR, optionally when REG OP N");
+  println!("                        holds (OP is ==, !=, <, <=, > or >=)");
+  println!("  w ADDR[-END] [r|w|rw] watch memory accesses (default writes)");
+  println!("  d N, dw N             delete breakpoint or watchpoint N");
+  println!("  i                     list breakpoints and watchpoints");
+  println!("  s [N]                 step N instructions (default 1)");
+  println!("  n                     step over calls");
+  println!("  f                     run until the current routine returns");
+  println!("  c                     continue");
+  println!("  r                     registers");
+  println!("  x ADDR [LEN]          dump memory (default 64 bytes)");
+  println!("  u [N]                 disassemble around PC (default 8)");
+  println!("  io                    sound and video registers");
+  println!("  q                     quit");
+  println!("n, f and c give up after {} emulated seconds.",
+           RUN_LIMIT / GB_FREQ as u64);
+  println!("An empty line repeats the last command.");
+}
+
+fn main() {
+  let mut positional = Vec::new();
+  let mut sym = None;
+  let mut model = Model::Dmg;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--sym" => sym = Some(args.next().unwrap_or_else(|| usage())),
+      "--model" => {
+        model = args.next().and_then(|s| Model::parse(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "-h" | "--help" => usage(),
+      _ => positional.push(arg),
+    }
+  }
+
+  let filename = positional.first().unwrap_or_else(|| usage());
+  let track = positional.get(1)
+    .map(|s| parse_u8(s).unwrap_or_else(|| usage()))
+    .unwrap_or(0);
+
+  let symbols = match sym {
+    Some(path) => symbols::load(path),
+    None => symbols::load_beside(filename),
+  }.expect("Error loading symbol file");
+
+  if Path::new(filename).extension().is_some_and(|e| e == "gbs") {
+    let gbs = gbs_parser::load(filename).expect("Error loading GBS file");
+    if track >= gbs.n_songs {
+      panic!("Requested track {} but only {} are available", track, gbs.n_songs);
+    }
+    session(Debugger::from_gbs(&gbs, track), symbols);
+  } else {
+    let rom = gb_parser::load(filename).expect("Error loading GB file");
+    session(Debugger::from_rom(&rom.rom, model), symbols);
+  }
+}
+
+// Read commands until the end of input
+fn session<B>(mut dbg: Debugger<B>, symbols: Symbols)
+  where B: Bus + AsRef<Hardware> + AsMut<Hardware> {
+  dbg.gb.cpu.symbols = symbols;
+  println!("{}", dbg.registers());
+  println!("{}", dbg.disassemble(dbg.pc()).0);
+
+  let stdin = io::stdin();
+  let mut last = String::new();
+  loop {
+    print!("> ");
+    io::stdout().flush().unwrap();
+
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+      break;
+    }
+    let line = match line.trim() {
+      "" => last.clone(),
+      l => l.to_string(),
+    };
+    last = line.clone();
+
+    let args : Vec<&str> = line.split_whitespace().collect();
+    match args.first() {
+      Some(&"q") => break,
+      Some(_) if command(&mut dbg, &args).is_none() =>
+        println!("Bad command, try h for help"),
+      _ => {},
+    }
+  }
+}
+
+// Run one command.  Return None if it is malformed.
+fn command<B>(dbg: &mut Debugger<B>, args: &[&str]) -> Option<()>
+  where B: Bus + AsRef<Hardware> + AsMut<Hardware> {
+  let arg = |i: usize| args.get(i).cloned();
+
+  match args[0] {
+    "h" | "help" => help(),
+
+    "b" => {
+      let addr = parse_addr(dbg, arg(1)?)?;
+      let condition = match arg(2) {
+        Some("if") => Some(Condition {
+          reg: Reg::parse(arg(3)?)?,
+          cmp: Cmp::parse(arg(4)?)?,
+          value: parse_u16(arg(5)?)?,
+        }),
+        Some(_) => return None,
+        None => None,
+      };
+      dbg.breakpoints.push(Breakpoint { addr, condition });
+      println!("Breakpoint {} at {}", dbg.breakpoints.len() - 1,
+               dbg.gb.cpu.symbols.describe(addr));
+    }
+
+    "w" => {
+      let range = arg(1)?;
+      let (start, end) = match range.find('-') {
+        Some(i) => (parse_addr(dbg, &range[..i])?,
+                    parse_addr(dbg, &range[i + 1..])?),
+        None => (parse_addr(dbg, range)?, parse_addr(dbg, range)?),
+      };
+      let (read, write) = match arg(2) {
+        Some("r") => (true, false),
+        Some("w") | None => (false, true),
+        Some("rw") => (true, true),
+        Some(_) => return None,
+      };
+      let cpu = &mut dbg.gb.cpu;
+      cpu.bus.as_mut().watchpoints.push(Watchpoint { start, end, read, write });
+      println!("Watchpoint {} at {}", cpu.bus.as_ref().watchpoints.len() - 1,
+               describe_range(&cpu.symbols, start, end));
+    }
+
+    "d" => {
+      let n = arg(1)?.parse::<usize>().ok()?;
+      if n >= dbg.breakpoints.len() {
+        return None;
+      }
+      dbg.breakpoints.remove(n);
+    }
+
+    "dw" => {
+      let n = arg(1)?.parse::<usize>().ok()?;
+      let watchpoints = &mut dbg.gb.cpu.bus.as_mut().watchpoints;
+      if n >= watchpoints.len() {
+        return None;
+      }
+      watchpoints.remove(n);
+    }
+
+    "i" => {
+      let symbols = &dbg.gb.cpu.symbols;
+      for (i, b) in dbg.breakpoints.iter().enumerate() {
+        match b.condition {
+          Some(c) => println!("Breakpoint {} at {} if {:?} {:?} {:04X}",
+                              i, symbols.describe(b.addr), c.reg, c.cmp,
+                              c.value),
+          None => println!("Breakpoint {} at {}", i, symbols.describe(b.addr)),
+        }
+      }
+      for (i, w) in dbg.gb.cpu.bus.as_ref().watchpoints.iter().enumerate() {
+        println!("Watchpoint {} at {}{}{}", i,
+                 describe_range(symbols, w.start, w.end),
+                 if w.read { " read" } else { "" },
+                 if w.write { " write" } else { "" });
+      }
+    }
+
+    "s" => {
+      let n = match arg(1) {
+        Some(n) => n.parse().ok()?,
+        None => 1,
+      };
+      let stop = dbg.step(n);
+      report(dbg, stop);
+    }
+
+    "n" => {
+      let stop = dbg.step_over();
+      report(dbg, stop);
+    }
+
+    "f" => {
+      let stop = dbg.step_out();
+      report(dbg, stop);
+    }
+
+    "c" => {
+      let stop = dbg.cont();
+      report(dbg, stop);
+    }
+
+    "r" => println!("{}", dbg.registers()),
+
+    "x" => {
+      let addr = parse_addr(dbg, arg(1)?)?;
+      let len = match arg(2) {
+        Some(n) => parse_u16(n)?,
+        None => 64,
+      };
+      for line in dbg.dump(addr, len) {
+        println!("{}", line);
+      }
+    }
+
+    "u" => {
+      let n = match arg(1) {
+        Some(n) => parse_u16(n)?,
+        None => 8,
+      };
+      for line in dbg.disassemble_around_pc(n) {
+        println!("{}", line);
+      }
+    }
+
+    "io" => {
+      for line in dbg.io() {
+        println!("{}", line);
+      }
+    }
+
+    _ => return None,
+  }
+
+  Some(())
+}
+
+fn report<B: Bus + AsRef<Hardware>>(dbg: &Debugger<B>, stop: Stop) {
+  match stop {
+    Stop::Step => {},
+    Stop::Breakpoint(n) => println!("Breakpoint {}", n),
+    Stop::Watchpoint(hit) => {
+      let access = match hit.access {
+        Access::Read => "Read",
+        Access::Write => "Write",
+      };
+      println!("{} of {:02X} at {}", access, hit.value,
+               dbg.gb.cpu.symbols.describe(hit.addr));
+    }
+    Stop::Lockup(lockup) => {
+      println!("Locked up by illegal opcode {:02X} at {}", lockup.opcode,
+               dbg.gb.cpu.symbols.describe(lockup.addr));
+    }
+    Stop::Limit => println!("Still running after {} cycles", RUN_LIMIT),
+  }
+  println!("{}", dbg.disassemble(dbg.pc()).0);
+}
+
+fn describe_range(symbols: &Symbols, start: u16, end: u16) -> String {
+  if start == end {
+    symbols.describe(start)
+  } else {
+    format!("{}-{}", symbols.describe(start), symbols.describe(end))
+  }
+}
+
+// A number or a label
+fn parse_addr<B>(dbg: &Debugger<B>, s: &str) -> Option<u16> {
+  parse_u16(s).or_else(|| dbg.gb.cpu.symbols.addr(s))
+}
@@ -0,0 +1,127 @@
+extern crate gbs;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gbs::disassembler;
+use gbs::disassembler::listing::Listing;
+use gbs::gb_parser;
+use gbs::gbs_parser;
+use gbs::symbols;
+use gbs::util::parse_u16;
+
+const BANK_SIZE : usize = 0x4000;
+
+fn usage() -> ! {
+  println!("Usage: gb-disasm FILE [START [END]] [--bank N] [--sym PATH]");
+  println!("       gb-disasm FILE.gbs --asm [-o PATH] [--sym PATH]");
+  println!();
+  println!("Disassemble a GBS file or a ROM from START to END (inclusive), in");
+  println!("RGBDS syntax.  Numbers are decimal, or hexadecimal with a 0x or $");
+  println!("prefix.");
+  println!();
+  println!("  START, END  addresses; default to the whole GBS data, or $0000-$7FFF");
+  println!("  --bank N    ROM bank mapped at $4000 (default 1)");
+  println!("  --asm       trace the code of a GBS driver from INIT and PLAY, and");
+  println!("              write an RGBDS listing that reassembles to the same");
+  println!("              bytes");
+  println!("  -o PATH     write the listing to PATH rather than the standard output");
+  println!("  --sym PATH  name addresses with the labels of a symbol file, as");
+  println!("              written by rgblink (default FILE.sym, if present)");
+  std::process::exit(1);
+}
+
+fn main() {
+  let mut positional = Vec::new();
+  let mut bank = 1;
+  let mut asm = false;
+  let mut output = None;
+  let mut sym = None;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--bank" => {
+        bank = args.next().and_then(|s| parse_u16(&s))
+          .unwrap_or_else(|| usage()) as usize;
+      }
+      "--asm" => asm = true,
+      "-o" => output = Some(args.next().unwrap_or_else(|| usage())),
+      "--sym" => sym = Some(args.next().unwrap_or_else(|| usage())),
+      "-h" | "--help" => usage(),
+      _ => positional.push(arg),
+    }
+  }
+
+  let filename = positional.first().unwrap_or_else(|| usage());
+  let mut symbols = match sym {
+    Some(path) => symbols::load(path),
+    None => symbols::load_beside(filename),
+  }.expect("Error loading symbol file");
+
+  if asm {
+    let gbs = gbs_parser::load(filename).expect("Error loading GBS file");
+    let mut out : Box<dyn Write> = match output {
+      Some(path) => Box::new(BufWriter::new(File::create(path)
+                                            .expect("Cannot create output file"))),
+      None => Box::new(io::stdout()),
+    };
+    writeln!(out, "; {} - {} ({})", gbs.title, gbs.author, gbs.copyright)
+      .and_then(|_| writeln!(out, "; Load ${:04X}, init ${:04X}, play ${:04X}",
+                             gbs.load_addr, gbs.init_addr, gbs.play_addr))
+      .and_then(|_| Listing::from_gbs(&gbs, &symbols).write_asm(&mut out))
+      .and_then(|_| out.flush())
+      .expect("Error writing listing");
+    return;
+  }
+
+  // The memory image to disassemble, starting at base
+  let (base, image) = if Path::new(filename).extension().is_some_and(|e| e == "gbs") {
+    let gbs = gbs_parser::load(filename).expect("Error loading GBS file");
+    (gbs.load_addr, gbs.rom)
+  } else {
+    let rom = gb_parser::load(filename).expect("Error loading GB file").rom;
+    let mut image = rom[..rom.len().min(BANK_SIZE)].to_vec();
+    let start = bank * BANK_SIZE;
+    if start < rom.len() {
+      image.extend_from_slice(&rom[start..rom.len().min(start + BANK_SIZE)]);
+    }
+    symbols.rom_bank = bank as u16;
+    (0, image)
+  };
+
+  let image_end = base as usize + image.len();
+  let arg = |i: usize| positional.get(i)
+    .map(|s| parse_u16(s).unwrap_or_else(|| usage()) as usize);
+  let start = arg(1).unwrap_or(base as usize);
+  let end = arg(2).map(|e| e + 1).unwrap_or(image_end).min(image_end);
+
+  if start < base as usize || start >= end {
+    println!("Range outside of the file ({:04X}-{:04X})", base, image_end - 1);
+    std::process::exit(1);
+  }
+
+  let bytes = &image[start - base as usize..end - base as usize];
+  let insts = disassembler::disassemble(bytes, start as u16);
+
+  for inst in &insts {
+    if let Some((label, 0)) = symbols.lookup(inst.addr) {
+      println!("{}:", label);
+    }
+    let text = inst.format_with(|a| symbols.name(a));
+    let hex = inst.bytes().iter()
+      .map(|b| format!("{:02X}", b))
+      .collect::<Vec<_>>()
+      .join(" ");
+    println!("  {:<24} ; {:04X}: {}", text, inst.addr, hex);
+  }
+
+  // Bytes cut off by the end of the range
+  let decoded = insts.iter().map(|i| i.length as usize).sum::<usize>();
+  for (i, b) in bytes[decoded..].iter().enumerate() {
+    println!("  {:<24} ; {:04X}", format!("db ${:02x}", b),
+             start + decoded + i);
+  }
+}
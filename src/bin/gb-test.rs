@@ -0,0 +1,147 @@
+extern crate gbs;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use gbs::gb::GB_FREQ;
+use gbs::gb::model::Model;
+use gbs::gb_parser;
+use gbs::test_runner::{self, Outcome};
+
+const DEFAULT_TIMEOUT : u64 = 30;
+
+// Exit codes, the worst result wins
+const EXIT_PASSED : i32 = 0;
+const EXIT_FAILED : i32 = 1;
+const EXIT_TIMEOUT : i32 = 2;
+const EXIT_ERROR : i32 = 3;
+
+fn usage() -> ! {
+  println!("Usage: gb-test PATH... [--timeout SECONDS] [--model MODEL] [-v]");
+  println!();
+  println!("Run blargg's or Mooneye's test ROMs without a screen, and print");
+  println!("their results.  A directory runs every .gb and .gbc file in it,");
+  println!("recursively.");
+  println!();
+  println!("  --timeout SECONDS  emulated seconds before a ROM times out");
+  println!("                     (default {})", DEFAULT_TIMEOUT);
+  println!("  --model MODEL      model whose boot ROM state to start in: dmg");
+  println!("                     (default), mgb, sgb or cgb");
+  println!("  -v                 print the whole output of each ROM");
+  println!();
+  println!("Exits with 0 when all ROMs passed, 1 when one failed, 2 when one");
+  println!("timed out, and 3 when one could not be loaded or needs bank");
+  println!("switching.");
+  process::exit(EXIT_ERROR);
+}
+
+fn main() {
+  let mut paths = Vec::new();
+  let mut timeout = DEFAULT_TIMEOUT;
+  let mut model = Model::Dmg;
+  let mut verbose = false;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--timeout" => {
+        timeout = args.next().and_then(|s| s.parse().ok())
+          .unwrap_or_else(|| usage());
+      }
+      "--model" => {
+        model = args.next().and_then(|s| Model::parse(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "-v" => verbose = true,
+      "-h" | "--help" => usage(),
+      _ => paths.push(PathBuf::from(arg)),
+    }
+  }
+  if paths.is_empty() {
+    usage();
+  }
+
+  let mut roms = Vec::new();
+  for path in &paths {
+    if path.is_dir() {
+      find_roms(path, &mut roms);
+    } else {
+      roms.push(path.clone());
+    }
+  }
+  roms.sort();
+
+  let width = roms.iter().map(|p| p.display().to_string().len())
+    .max().unwrap_or(0).max(3);
+  println!("{:<w$}  {:<7}  {:>7}  Message", "ROM", "Result", "Time", w = width);
+
+  let mut exit = EXIT_PASSED;
+  let mut counts = [0; 4];
+
+  for path in &roms {
+    let name = path.display().to_string();
+    let report = gb_parser::load(path)
+      .map_err(|e| e.to_string())
+      .and_then(|gb| {
+        test_runner::run(&gb.rom, model, timeout * GB_FREQ as u64)
+          .map_err(|e| e.to_string())
+      });
+    let report = match report {
+      Ok(report) => report,
+      Err(e) => {
+        println!("{:<w$}  {:<7}  {:>7}  {}", name, "error", "", e, w = width);
+        counts[EXIT_ERROR as usize] += 1;
+        exit = exit.max(EXIT_ERROR);
+        continue;
+      }
+    };
+
+    let (result, code) = match report.outcome {
+      Outcome::Passed => ("passed", EXIT_PASSED),
+      Outcome::Failed => ("FAILED", EXIT_FAILED),
+      Outcome::Timeout => ("TIMEOUT", EXIT_TIMEOUT),
+    };
+    let seconds = report.cycles as f64 / GB_FREQ as f64;
+    // The verdict is usually on the last line
+    let message = report.output.lines().last().unwrap_or("");
+    println!("{:<w$}  {:<7}  {:>6.1}s  {}", name, result, seconds, message,
+             w = width);
+    if verbose && !report.output.is_empty() {
+      for line in report.output.lines() {
+        println!("    {}", line);
+      }
+    }
+
+    counts[code as usize] += 1;
+    exit = exit.max(code);
+  }
+
+  if roms.len() > 1 {
+    println!();
+    println!("{} passed, {} failed, {} timed out, {} not run",
+             counts[EXIT_PASSED as usize], counts[EXIT_FAILED as usize],
+             counts[EXIT_TIMEOUT as usize], counts[EXIT_ERROR as usize]);
+  }
+
+  process::exit(exit);
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) => {
+      eprintln!("Cannot read {}: {}", dir.display(), e);
+      return;
+    }
+  };
+
+  for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+    if path.is_dir() {
+      find_roms(&path, roms);
+    } else if path.extension().is_some_and(|e| e == "gb" || e == "gbc") {
+      roms.push(path);
+    }
+  }
+}
@@ -3,23 +3,70 @@ extern crate gbs;
 use std::env;
 
 use gbs::gb_parser;
+use gbs::symbols;
 use gbs::screen;
 use gbs::gb::{self, lcd};
+use gbs::gb::model::Model;
+use gbs::gb::serial;
+use gbs::gb::trace::{self, Tracer, Trigger};
 
 #[macro_use]
 extern crate glium;
 
-// use glium::glutin::{Event, ElementState, VirtualKeyCode, MouseButton,
-//                     MouseScrollDelta, TouchPhase};
+use glium::glutin::{Event, ElementState};
 use glium::DisplayBuild;
 
 const SCREEN_ZOOM: usize = 4;
 
+// Usage: gb FILE [--model dmg|mgb|sgb|cgb] [--serial PEER] [--trace PATH]
+//              [--trace-format doctor|disasm]
+//              [--trace-start pc=ADDR|cycle=N] [--trace-stop pc=ADDR|cycle=N]
+//
+// PEER is plugged into the serial port: null (default), loopback, log:PATH,
+// printer:DIR, tcp-listen:[HOST:]PORT, tcp:HOST:PORT, unix-listen:PATH or
+// unix:PATH.  Link two instances with tcp-listen on one and tcp on the other;
+// tcp-listen only accepts local connections unless given a HOST, such as
+// 0.0.0.0 for every interface.  The printer writes each print job as a PNG
+// file in DIR.
+//
+// Doctor traces make LY always read $90, like the emulator that made the
+// Gameboy Doctor logs, so that they can be compared.
 fn main() {
-  let filename = env::args().nth(1)
-    .expect("No GB file specified");
+  let mut filename = None;
+  let mut model = Model::Dmg;
+  let mut peer = None;
+  let mut trace = None;
+  let mut trace_format = trace::Format::Doctor;
+  let mut trace_start = None;
+  let mut trace_stop = None;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--model" => {
+        model = args.next().and_then(|s| Model::parse(&s))
+          .expect("Unknown model");
+      }
+      "--serial" => peer = Some(args.next().expect("No serial peer specified")),
+      "--trace" => trace = Some(args.next().expect("No trace file specified")),
+      "--trace-format" => {
+        trace_format = args.next().and_then(|s| trace::Format::parse(&s))
+          .expect("Unknown trace format");
+      }
+      "--trace-start" => {
+        trace_start = Some(args.next().and_then(|s| Trigger::parse(&s))
+                           .expect("Bad trace trigger"));
+      }
+      "--trace-stop" => {
+        trace_stop = Some(args.next().and_then(|s| Trigger::parse(&s))
+                          .expect("Bad trace trigger"));
+      }
+      _ => filename = Some(arg),
+    }
+  }
+  let filename = filename.expect("No GB file specified");
 
-  let gbs = gb_parser::load(filename)
+  let gbs = gb_parser::load(&filename)
     .expect("Error loading GB file");
 
   println!("Title: {}", gbs.title);
@@ -29,6 +76,26 @@ fn main() {
   let mut gb = gb::GB::new();
   // Load
   gb.load_rom(&gbs.rom, 0);
+  gb.cpu.symbols = symbols::load_beside(&filename)
+    .expect("Error loading symbol file");
+
+  if let Some(spec) = peer {
+    gb.cpu.bus.serial.peer = serial::open(&spec)
+      .expect("Cannot open serial peer");
+  }
+
+  if let Some(path) = trace {
+    let mut tracer = Tracer::create(path, trace_format)
+      .expect("Cannot create trace file");
+    if let Some(t) = trace_start {
+      tracer = tracer.start_at(t);
+    }
+    if let Some(t) = trace_stop {
+      tracer = tracer.stop_at(t);
+    }
+    gb.cpu.tracer = Some(tracer);
+    gb.cpu.bus.doctor_ly = trace_format == trace::Format::Doctor;
+  }
 
   // Init screen
   let display = glium::glutin::WindowBuilder::new()
@@ -40,12 +107,31 @@ fn main() {
 
   let lcd = lcd::LCD::new();
 
-  // Init
-  gb.reset();
+  // Start at the cartridge entry point, as the boot ROM leaves it
+  gb.reset_to(model);
 
   // Play
+  let mut slot = 1;
+  // Report a lockup once, and keep showing the screen
+  let mut locked = false;
   loop {
-    gb.run_for(70224);
+    match gb.run_for(70224) {
+      Err(lockup) if !locked => {
+        println!("CPU locked up: {}", lockup);
+        locked = true;
+      }
+      Err(_) => {},
+      Ok(()) => locked = false,
+    }
+
+    for event in display.poll_events() {
+      match event {
+        Event::Closed => return,
+        Event::KeyboardInput(ElementState::Pressed, _, Some(key)) =>
+          screen::hotkey(&mut gb, &filename, &mut slot, key),
+        _ => {},
+      }
+    }
 
     let mut frame = display.draw();
 
@@ -1,24 +1,481 @@
 extern crate gbs;
-extern crate hound;
 
 use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use gbs::gbs_parser;
-use gbs::gb::GB;
-use gbs::gb::cpu::{R8, R16};
+use gbs::gb::coverage::Coverage;
+use gbs::gb::trace::{self, Tracer, Trigger};
+use gbs::gb_parser;
+use gbs::gbs_parser::{self, Gbs};
+use gbs::ripper::{self, Rip};
+use gbs::playlist_parser::{self, parse_time};
+use gbs::profiler::Profiler;
+use gbs::player::{GbsPlayer, PlayerError};
+use gbs::symbols::{self, Symbols};
+use gbs::sink::{self, Channels, Clipping, Container, SampleFormat, Sink, Spec,
+                Tags};
+use gbs::util::{parse_u8, parse_u16};
+
+// Used when neither the command line nor the playlist give a length
+const DEFAULT_LENGTH : u32 = 60 * 1000;
+
+fn usage() -> ! {
+  println!("Usage: gbs FILE [TRACK] [OPTIONS]");
+  println!("       gbs PLAYLIST.m3u [OPTIONS]");
+  println!();
+  println!("Options:");
+  println!("  -o PATH          output file (default out.wav); the extension");
+  println!("                   selects the container: .wav, .aiff or .raw.");
+  println!("                   Playlist tracks are numbered: out-01.wav, ...");
+  println!("  --format FORMAT  int16 (default), int24 or float32");
+  println!("  --rate HZ        sample rate (default 44100)");
+  println!("  --mono           downmix to a single channel");
+  println!("  --limit          use a limiter rather than clipping loud samples");
+  println!("  --length TIME    play time, as [[h:]m:]s (default 1:00)");
+  println!("  --fade TIME      fade out after the play time (default none)");
+  println!("  --start TIME     skip to that time in the track before rendering");
+  println!("  --trace PATH     write the instructions run by the driver to PATH");
+  println!("                   (for the first track of a playlist)");
+  println!("  --trace-format F doctor (default), for Gameboy Doctor logs, or");
+  println!("                   disasm");
+  println!("  --trace-start T  start tracing when T holds: pc=ADDR or cycle=N");
+  println!("  --trace-stop T   stop tracing when T holds");
+  println!();
+  println!("Labels from FILE.sym, as written by rgblink, name the addresses in");
+  println!("disasm traces.  The debug feature traces to the standard output.");
+  println!();
+  println!("       gbs edit FILE [EDIT OPTIONS]");
+  println!();
+  println!("Edit options (numbers can be hexadecimal with a 0x or $ prefix):");
+  println!("  --title TEXT, --author TEXT, --copyright TEXT");
+  println!("  --songs N        number of songs");
+  println!("  --first N        first song (1-based)");
+  println!("  --timer-mod N    timer modulo (TMA)");
+  println!("  --timer-ctrl N   timer control (TAC)");
+  println!("  -o PATH          write to PATH rather than FILE");
+  println!();
+  println!("       gbs rip ROM --load ADDR --init ADDR --play ADDR [RIP OPTIONS]");
+  println!();
+  println!("Rip options (and all edit options):");
+  println!("  --sp ADDR        stack pointer (default $FFFE)");
+  println!("  --rst            prepend the ROM RST vectors to the driver");
+  println!("  --keep-banks N   keep only the first N 16K banks, without checking");
+  println!("                   that the songs do not use the others");
+  println!("  --keep-used TIME play every song for TIME and drop the trailing");
+  println!("                   banks none of them touched; banks past $7FFF");
+  println!("                   are never mapped, so they are always kept");
+  println!("  --trim           drop the padding at the end of the ROM");
+  println!("  --preview TIME   length of the test render of each song (default");
+  println!("                   0:05), written next to the GBS file; 0 to skip");
+  println!("  -o PATH          GBS file to write (default out.gbs)");
+  println!();
+  println!("       gbs profile FILE [TRACK] [PROFILE OPTIONS]");
+  println!();
+  println!("Report the cycles taken by PLAY and by each routine it calls.");
+  println!("Profile options:");
+  println!("  --length TIME    play time (default 1:00)");
+  println!("  -o PATH          also write the call stacks to PATH, in the");
+  println!("                   collapsed format of flamegraph.pl");
+  println!();
+  println!("       gbs coverage FILE [COVERAGE OPTIONS]");
+  println!();
+  println!("Play every track and report which bytes of the GBS data were");
+  println!("executed, read or written.");
+  println!("Coverage options:");
+  println!("  --length TIME    play time of each track (default 1:00)");
+  println!("  -o PATH          also write a mask to PATH: one byte per byte of");
+  println!("                   data, bit 0 set if executed, 1 if read, 2 if");
+  println!("                   written, 7 if past $7FFF and never mapped");
+  std::process::exit(1);
+}
 
 fn main() {
+  match env::args().nth(1).as_deref() {
+    Some("edit") => return edit(env::args().skip(2)),
+    Some("rip") => return rip(env::args().skip(2)),
+    Some("profile") => return profile(env::args().skip(2)),
+    Some("coverage") => return coverage(env::args().skip(2)),
+    _ => {},
+  }
+
   // Parse args
-  let filename = env::args().nth(1)
-    .expect("No GBS file specified");
+  let mut positional = Vec::new();
+  let mut output = String::from("out.wav");
+  let mut spec = Spec::new();
+  let mut length = None;
+  let mut fade = None;
+  let mut start = 0;
+  let mut trace = None;
+  let mut trace_format = trace::Format::Doctor;
+  let mut trace_start = None;
+  let mut trace_stop = None;
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-o" => output = args.next().unwrap_or_else(|| usage()),
+      "--format" => {
+        spec.format = match args.next().as_deref() {
+          Some("int16") => SampleFormat::Int16,
+          Some("int24") => SampleFormat::Int24,
+          Some("float32") => SampleFormat::Float32,
+          _ => usage(),
+        }
+      }
+      "--rate" => {
+        spec.sample_rate = args.next().and_then(|s| s.parse().ok())
+          .unwrap_or_else(|| usage());
+      }
+      "--mono" => spec.channels = Channels::Mono,
+      "--limit" => spec.clipping = Clipping::Limit,
+      "--length" => {
+        length = Some(args.next().and_then(|s| parse_time(&s))
+                      .unwrap_or_else(|| usage()));
+      }
+      "--fade" => {
+        fade = Some(args.next().and_then(|s| parse_time(&s))
+                    .unwrap_or_else(|| usage()));
+      }
+      "--start" => {
+        start = args.next().and_then(|s| parse_time(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "--trace" => trace = Some(args.next().unwrap_or_else(|| usage())),
+      "--trace-format" => {
+        trace_format = args.next().and_then(|s| trace::Format::parse(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "--trace-start" => {
+        trace_start = Some(args.next().and_then(|s| Trigger::parse(&s))
+                           .unwrap_or_else(|| usage()));
+      }
+      "--trace-stop" => {
+        trace_stop = Some(args.next().and_then(|s| Trigger::parse(&s))
+                          .unwrap_or_else(|| usage()));
+      }
+      "-h" | "--help" => usage(),
+      _ => positional.push(arg),
+    }
+  }
+
+  let filename = positional.first().unwrap_or_else(|| usage());
+
+  let mut tracer = trace.map(|path| {
+    let mut tracer = Tracer::create(path, trace_format)
+      .expect("Cannot create trace file");
+    if let Some(t) = trace_start {
+      tracer = tracer.start_at(t);
+    }
+    if let Some(t) = trace_stop {
+      tracer = tracer.stop_at(t);
+    }
+    tracer
+  });
+
+  let container = Container::from_path(&output)
+    .unwrap_or_else(|| panic!("Unknown output format for {}", output));
+
+  let extension = Path::new(filename).extension();
+  if extension.is_some_and(|e| e.eq_ignore_ascii_case("m3u")) {
+    let playlist = playlist_parser::load(filename)
+      .expect("Error loading playlist");
+    // Files in the playlist are relative to it
+    let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+
+    for (i, entry) in playlist.entries.iter().enumerate() {
+      let gbs = gbs_parser::load(dir.join(&entry.file))
+        .expect("Error loading GBS file");
+      let symbols = symbols::load_beside(dir.join(&entry.file))
+        .expect("Error loading symbol file");
+
+      if entry.track >= gbs.n_songs {
+        println!("Skipping {}: track {} but only {} are available",
+                 entry.title, entry.track + 1, gbs.n_songs);
+        continue;
+      }
+
+      let mut tags = Tags::from_gbs(&gbs, entry.track);
+      if !entry.title.is_empty() {
+        tags.title = entry.title.clone();
+      }
+
+      let path = numbered(&output, i + 1);
+      println!("Writing {} (track {}) to {}...", tags.title, entry.track + 1,
+               path.display());
 
-  let track = env::args().nth(2).map(|s| s.parse::<u8>())
-    .unwrap_or(Ok(0)).unwrap_or(0);
+      let mut sink = sink::create(&path, container, spec)
+        .expect("Cannot create output file");
+      sink.tag(&tags);
+      open(&gbs, &symbols, tracer.take(), entry.track, spec)
+        .and_then(|mut player| {
+          render(&mut player, start,
+                 length.or(entry.length).unwrap_or(DEFAULT_LENGTH),
+                 fade.or(entry.fade).unwrap_or(0), &mut *sink)
+        })
+        .unwrap_or_else(|e| println!("Error: {}", e));
+      sink.finish().expect("Error writing samples");
+    }
+  } else {
+    let track = positional.get(1).map(|s| s.parse::<u8>())
+      .unwrap_or(Ok(0)).unwrap_or(0);
+
+    // Read GBS file
+    let gbs = gbs_parser::load(filename)
+      .expect("Error loading GBS file");
+    let symbols = symbols::load_beside(filename)
+      .expect("Error loading symbol file");
+
+    print_header(&gbs);
 
-  // Read GBS file
-  let gbs = gbs_parser::load(filename)
+    // Bail if track doesn't exist
+    if track >= gbs.n_songs {
+      panic!("Requested track {} but only {} are available", track, gbs.n_songs);
+    } else {
+      println!("Writing track {} to {}...", track, output);
+    }
+
+    // Init output
+    let mut sink = sink::create(&output, container, spec)
+      .expect("Cannot create output file");
+    sink.tag(&Tags::from_gbs(&gbs, track));
+
+    open(&gbs, &symbols, tracer, track, spec)
+      .and_then(|mut player| {
+        render(&mut player, start, length.unwrap_or(DEFAULT_LENGTH),
+               fade.unwrap_or(0), &mut *sink)
+      })
+      .unwrap_or_else(|e| println!("Error: {}", e));
+    sink.finish().expect("Error writing samples");
+  }
+
+  println!("Done");
+}
+
+// Rewrite header fields of a GBS file
+fn edit<I: Iterator<Item=String>>(mut args: I) {
+  let filename = args.next().unwrap_or_else(|| usage());
+  let mut gbs = gbs_parser::load(&filename)
     .expect("Error loading GBS file");
+  let mut output = filename.clone();
 
+  let string = |s: Option<String>| {
+    let s = s.unwrap_or_else(|| usage());
+    if s.chars().count() > 32 {
+      println!("Warning: {} is longer than 32 characters and will be cut", s);
+    }
+    s
+  };
+  let number = |s: Option<String>| {
+    s.as_deref().and_then(parse_u8).unwrap_or_else(|| usage())
+  };
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--title" => gbs.title = string(args.next()),
+      "--author" => gbs.author = string(args.next()),
+      "--copyright" => gbs.copyright = string(args.next()),
+      "--songs" => gbs.n_songs = number(args.next()),
+      "--first" => gbs.first_song = number(args.next()),
+      "--timer-mod" => gbs.timer_mod = number(args.next()),
+      "--timer-ctrl" => gbs.timer_ctrl = number(args.next()),
+      "-o" => output = args.next().unwrap_or_else(|| usage()),
+      _ => usage(),
+    }
+  }
+
+  if gbs.n_songs == 0 || gbs.first_song == 0 || gbs.first_song > gbs.n_songs {
+    println!("Warning: first song {} is not in 1..{}",
+             gbs.first_song, gbs.n_songs);
+  }
+
+  gbs_parser::save(&gbs, &output).expect("Error writing GBS file");
+  print_header(&gbs);
+  println!("Wrote {}", output);
+}
+
+// Build a GBS file from a ROM, then render a few seconds of each song
+fn rip<I: Iterator<Item=String>>(mut args: I) {
+  let filename = args.next().unwrap_or_else(|| usage());
+  let rom = gb_parser::load(&filename)
+    .expect("Error loading GB file");
+  let symbols = symbols::load_beside(&filename)
+    .expect("Error loading symbol file");
+
+  let mut rip = Rip::new(0, 0, 0);
+  let mut output = String::from("out.gbs");
+  let mut preview = 5 * 1000;
+  let mut keep_used = None;
+
+  let string = |s: Option<String>| s.unwrap_or_else(|| usage());
+  let addr = |s: Option<String>| {
+    s.as_deref().and_then(parse_u16).unwrap_or_else(|| usage())
+  };
+  let number = |s: Option<String>| {
+    s.as_deref().and_then(parse_u8).unwrap_or_else(|| usage())
+  };
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--load" => rip.load_addr = addr(args.next()),
+      "--init" => rip.init_addr = addr(args.next()),
+      "--play" => rip.play_addr = addr(args.next()),
+      "--sp" => rip.sp = addr(args.next()),
+      "--title" => rip.title = string(args.next()),
+      "--author" => rip.author = string(args.next()),
+      "--copyright" => rip.copyright = string(args.next()),
+      "--songs" => rip.n_songs = number(args.next()),
+      "--first" => rip.first_song = number(args.next()),
+      "--timer-mod" => rip.timer_mod = number(args.next()),
+      "--timer-ctrl" => rip.timer_ctrl = number(args.next()),
+      "--rst" => rip.rst_patch = true,
+      "--keep-banks" => rip.keep_banks = Some(number(args.next()) as usize),
+      "--keep-used" => {
+        keep_used = Some(args.next().and_then(|s| parse_time(&s))
+                         .unwrap_or_else(|| usage()));
+      }
+      "--trim" => rip.trim = true,
+      "--preview" => {
+        preview = args.next().and_then(|s| parse_time(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "-o" => output = args.next().unwrap_or_else(|| usage()),
+      _ => usage(),
+    }
+  }
+
+  let mut gbs = rip.build(&rom.rom)
+    .unwrap_or_else(|e| panic!("Cannot build GBS file: {:?}", e));
+  if let Some(length) = keep_used {
+    let banks = ripper::used_banks(&gbs, length);
+    let kept = rip.keep_banks.map_or(banks, |n| n.min(banks));
+    rip.keep_banks = Some(kept);
+    gbs = rip.build(&rom.rom)
+      .unwrap_or_else(|e| panic!("Cannot build GBS file: {:?}", e));
+    println!("Keeping the first {} banks", kept);
+  }
+  gbs_parser::save(&gbs, &output).expect("Error writing GBS file");
+  print_header(&gbs);
+  println!("Wrote {}", output);
+
+  if preview == 0 {
+    return;
+  }
+
+  // Check the rip by playing it back
+  let spec = Spec::new();
+  for track in 0..gbs.n_songs {
+    let path = numbered(&output, track as usize + 1).with_extension("wav");
+    let mut sink = sink::create(&path, Container::Wav, spec)
+      .expect("Cannot create output file");
+    sink.tag(&Tags::from_gbs(&gbs, track));
+
+    let result = open(&gbs, &symbols, None, track, spec)
+      .and_then(|mut player| render(&mut player, 0, preview, 0, &mut *sink));
+    match result {
+      Ok(()) => println!("Track {}: OK, preview in {}", track, path.display()),
+      Err(e) => println!("Track {}: {}", track, e),
+    }
+    sink.finish().expect("Error writing samples");
+  }
+}
+
+// Run a track and report where PLAY spends its cycles
+fn profile<I: Iterator<Item=String>>(mut args: I) {
+  let filename = args.next().unwrap_or_else(|| usage());
+  let mut track = 0;
+  let mut length = DEFAULT_LENGTH;
+  let mut output = None;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--length" => {
+        length = args.next().and_then(|s| parse_time(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "-o" => output = Some(args.next().unwrap_or_else(|| usage())),
+      _ => track = arg.parse().unwrap_or_else(|_| usage()),
+    }
+  }
+
+  let gbs = gbs_parser::load(&filename)
+    .expect("Error loading GBS file");
+  let symbols = symbols::load_beside(&filename)
+    .expect("Error loading symbol file");
+
+  let mut player = open(&gbs, &symbols, None, track, Spec::new())
+    .unwrap_or_else(|e| panic!("Error: {}", e));
+  let budget = player.play_period();
+  player.set_profiler(Profiler::new(budget));
+  // Report what ran before a hang
+  if let Err(e) = player.seek(length) {
+    println!("Error: {}", e);
+  }
+
+  if let Some(profiler) = player.profiler() {
+    let stdout = io::stdout();
+    profiler.write_report(&mut stdout.lock(), &symbols)
+      .expect("Error writing report");
+
+    if let Some(path) = output {
+      let mut file = BufWriter::new(File::create(&path)
+                                    .expect("Cannot create output file"));
+      profiler.write_collapsed(&mut file, &symbols)
+        .and_then(|_| file.flush())
+        .expect("Error writing call stacks");
+      println!("Call stacks written to {}", path);
+    }
+  }
+}
+
+// Play every track and report the parts of the GBS data they use
+fn coverage<I: Iterator<Item=String>>(mut args: I) {
+  let filename = args.next().unwrap_or_else(|| usage());
+  let mut length = DEFAULT_LENGTH;
+  let mut output = None;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--length" => {
+        length = args.next().and_then(|s| parse_time(&s))
+          .unwrap_or_else(|| usage());
+      }
+      "-o" => output = Some(args.next().unwrap_or_else(|| usage())),
+      _ => usage(),
+    }
+  }
+
+  let gbs = gbs_parser::load(&filename)
+    .expect("Error loading GBS file");
+
+  let mut player = GbsPlayer::new(gbs.clone(), Spec::new().sample_rate);
+  player.set_coverage(Coverage::new());
+  for track in 0..gbs.n_songs {
+    // Count what ran before a hang
+    if let Err(e) = player.start_track(track)
+      .and_then(|_| player.seek(length)) {
+      println!("Track {}: {}", track, e);
+    }
+  }
+
+  if let Some(coverage) = player.coverage() {
+    let stdout = io::stdout();
+    coverage.write_summary(&mut stdout.lock(), gbs.load_addr, gbs.rom.len())
+      .expect("Error writing summary");
+
+    if let Some(path) = output {
+      let mut file = BufWriter::new(File::create(&path)
+                                    .expect("Cannot create output file"));
+      coverage.write_mask(&mut file, gbs.load_addr, gbs.rom.len())
+        .and_then(|_| file.flush())
+        .expect("Error writing mask");
+      println!("Mask written to {}", path);
+    }
+  }
+}
+
+fn print_header(gbs: &Gbs) {
   println!("load_addr: {:x}", gbs.load_addr);
   println!("init_addr: {:x}", gbs.init_addr);
   println!("play_addr: {:x}", gbs.play_addr);
@@ -33,85 +490,55 @@ fn main() {
   println!("author: {}", gbs.author);
   println!("copyright: {}", gbs.copyright);
   println!("rom len: {:x}", gbs.rom.len());
+}
 
-  // Bail if track doesn't exist
-  if track >= gbs.n_songs {
-    panic!("Requested track {} but only {} are available");
-  } else {
-    println!("Writing 1min of track {} to out.wav...", track);
+// out.wav -> out-01.wav
+fn numbered(path: &str, n: usize) -> PathBuf {
+  let path = Path::new(path);
+  let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+  let mut name = format!("{}-{:02}", stem, n);
+  if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+    name.push('.');
+    name.push_str(ext);
   }
+  path.with_file_name(name)
+}
 
-  // Init WAV output
-  let spec = hound::WavSpec {
-    channels: 2,
-    sample_rate: 44100,
-    bits_per_sample: 16,
-    sample_format: hound::SampleFormat::Int,
-  };
-  let max = std::i16::MAX as f32;
-  let mut writer = hound::WavWriter::create("out.wav", spec).unwrap();
-
-  // Init emu
-  let mut gb = GB::new();
-  let idle_addr = 0xF00D;
-  gb.cpu.rst_offset = gbs.load_addr;
-
-  // Load
-  gb.load_rom(&gbs.rom, gbs.load_addr);
-
-  // Init
-  gb.cpu.clear_registers();
-  gb.cpu.clear_ram();
-
-  gb.cpu.rr_set(R16::SP, gbs.sp);
-  gb.cpu.r_set(R8::A, track);
-  gb.cpu.rr_set(R16::PC, idle_addr);
-  gb.cpu.call(gbs.init_addr);
-  // Run the INIT subroutine
-  while gb.cpu.rr(R16::PC) != idle_addr {
-    gb.cpu.step();
-  }
-
-  // Play for 1min
-  let mut frames = 60 * 60;
-  let mut cycle = 0;
-  while frames > 0 {
-    // Emulate from play_addr at 60Hz
-    let mut frame_period = 70224u32;
-    gb.cpu.call(gbs.play_addr);
-
-    // Run until PLAY has finished
-    while gb.cpu.rr(R16::PC) != idle_addr {
-      let cycles = gb.cpu.step();
-      for _ in 0..cycles {
-        gb.cpu.hardware.apu_step();
-
-        // Downsample
-        if cycle % 95 == 0 {
-          let (left, right) = gb.cpu.hardware.apu_output();
-          writer.write_sample((left * max) as i16).unwrap();
-          writer.write_sample((right * max) as i16).unwrap();
-        }
-        cycle += 1;
-      }
-      frame_period -= cycles as u32;
-    }
+// A player started on track, tracing it if asked
+fn open(gbs: &Gbs, symbols: &Symbols, tracer: Option<Tracer>, track: u8,
+        spec: Spec) -> Result<GbsPlayer, PlayerError> {
+  let mut player = GbsPlayer::new(gbs.clone(), spec.sample_rate);
+  player.set_symbols(symbols.clone());
+  if let Some(tracer) = tracer {
+    player.set_tracer(tracer);
+  }
+  player.start_track(track)?;
+  Ok(player)
+}
 
-    // PLAY has finished for this frame, but we still need to run the APU until
-    // the next frame
-    for _ in 0..frame_period {
-      gb.cpu.hardware.apu_step();
+// Play from start for length milliseconds, then fade out for fade
+// milliseconds.  Fail if the driver hangs.
+fn render(player: &mut GbsPlayer, start: u32, length: u32, fade: u32,
+          sink: &mut dyn Sink) -> Result<(), PlayerError> {
+  player.seek(start)?;
 
-      // Downsample
-      if cycle % 95 == 0 {
-        let (left, right) = gb.cpu.hardware.apu_output();
-        writer.write_sample((left * max) as i16).unwrap();
-        writer.write_sample((right * max) as i16).unwrap();
-      }
-      cycle += 1;
-    }
-    frames -= 1;
+  let sample_rate = player.sample_rate() as u64;
+  let ms_to_samples = |ms: u32| ms as u64 * sample_rate / 1000;
+  let fade_start = ms_to_samples(length);
+  let fade_len = ms_to_samples(fade);
+
+  for n in 0..fade_start + fade_len {
+    let (left, right) = player.next_sample()?;
+
+    // Linear fade out
+    let gain = if n < fade_start {
+      1.0
+    } else {
+      1.0 - (n - fade_start) as f32 / fade_len as f32
+    };
+
+    sink.write(left * gain, right * gain).expect("Error writing samples");
   }
 
-  println!("Done");
+  Ok(())
 }
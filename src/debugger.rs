@@ -0,0 +1,421 @@
+// Core of the interactive debugger: run a machine until a breakpoint or a
+// watchpoint stops it, and describe its state.  The command line lives in the
+// gb-debug binary.
+
+use std::collections::VecDeque;
+
+use disassembler::{self, Instruction};
+use gb::{GB, GB_FREQ};
+use gb::bus::Bus;
+use gb::cpu::{Lockup, R8, R16};
+use gb::hardware::{Hardware, WatchHit};
+use gb::model::Model;
+use gbs_parser::Gbs;
+use player::{self, GbsBus, IDLE_ADDR};
+
+// Number of executed instructions kept for the disassembly around PC
+const HISTORY_SIZE : usize = 8;
+
+// Cycles that continuing or stepping over or out can run before giving the
+// hand back, in case nothing stops them: 10 seconds
+pub const RUN_LIMIT : u64 = 10 * GB_FREQ as u64;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Reg {
+  R8(R8),
+  R16(R16),
+}
+
+impl Reg {
+  pub fn parse(s: &str) -> Option<Self> {
+    Some(match s.to_uppercase().as_str() {
+      "A" => Reg::R8(R8::A),
+      "B" => Reg::R8(R8::B),
+      "C" => Reg::R8(R8::C),
+      "D" => Reg::R8(R8::D),
+      "E" => Reg::R8(R8::E),
+      "F" => Reg::R8(R8::F),
+      "H" => Reg::R8(R8::H),
+      "L" => Reg::R8(R8::L),
+      "AF" => Reg::R16(R16::AF),
+      "BC" => Reg::R16(R16::BC),
+      "DE" => Reg::R16(R16::DE),
+      "HL" => Reg::R16(R16::HL),
+      "SP" => Reg::R16(R16::SP),
+      "PC" => Reg::R16(R16::PC),
+      _ => return None,
+    })
+  }
+
+  fn value<B: Bus>(self, gb: &GB<B>) -> u16 {
+    match self {
+      Reg::R8(r) => gb.cpu.r(r) as u16,
+      Reg::R16(rr) => gb.cpu.rr(rr),
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cmp {
+  Eq, Ne, Lt, Le, Gt, Ge,
+}
+
+impl Cmp {
+  pub fn parse(s: &str) -> Option<Self> {
+    Some(match s {
+      "==" => Cmp::Eq,
+      "!=" => Cmp::Ne,
+      "<" => Cmp::Lt,
+      "<=" => Cmp::Le,
+      ">" => Cmp::Gt,
+      ">=" => Cmp::Ge,
+      _ => return None,
+    })
+  }
+}
+
+// A register compared to a constant, as in "A == $03"
+#[derive(Debug, Copy, Clone)]
+pub struct Condition {
+  pub reg: Reg,
+  pub cmp: Cmp,
+  pub value: u16,
+}
+
+impl Condition {
+  pub fn holds<B: Bus>(&self, gb: &GB<B>) -> bool {
+    let v = self.reg.value(gb);
+    match self.cmp {
+      Cmp::Eq => v == self.value,
+      Cmp::Ne => v != self.value,
+      Cmp::Lt => v < self.value,
+      Cmp::Le => v <= self.value,
+      Cmp::Gt => v > self.value,
+      Cmp::Ge => v >= self.value,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Breakpoint {
+  pub addr: u16,
+  pub condition: Option<Condition>,
+}
+
+// Why the machine stopped
+#[derive(Debug, Copy, Clone)]
+pub enum Stop {
+  // Done stepping
+  Step,
+  // Index in the breakpoint list
+  Breakpoint(usize),
+  Watchpoint(WatchHit),
+  Lockup(Lockup),
+  // Ran for RUN_LIMIT cycles
+  Limit,
+}
+
+pub struct Debugger<B = Hardware> {
+  pub gb: GB<B>,
+  pub breakpoints: Vec<Breakpoint>,
+  // Cycles run since the start
+  pub cycles: u64,
+  // Addresses of the last instructions executed, oldest first
+  history: VecDeque<u16>,
+  // For GBS files: PLAY is called again whenever the driver returns to the
+  // idle address
+  play_addr: Option<u16>,
+}
+
+impl Debugger {
+  // Debug a cartridge from its entry point, as the boot ROM of model leaves it
+  pub fn from_rom(rom: &[u8], model: Model) -> Self {
+    let mut gb = GB::new();
+    gb.load_rom(rom, 0);
+    gb.reset_to(model);
+    Debugger::new(gb, None)
+  }
+}
+
+impl Debugger<GbsBus> {
+  // Debug a GBS driver from the start of INIT for the given track
+  pub fn from_gbs(gbs: &Gbs, track: u8) -> Self {
+    Debugger::new(player::load_track(gbs, track), Some(gbs.play_addr))
+  }
+}
+
+impl<B: Bus + AsRef<Hardware>> Debugger<B> {
+  fn new(gb: GB<B>, play_addr: Option<u16>) -> Self {
+    Debugger {
+      gb,
+      breakpoints: Vec::new(),
+      cycles: 0,
+      history: VecDeque::new(),
+      play_addr,
+    }
+  }
+
+  pub fn pc(&self) -> u16 {
+    self.gb.cpu.rr(R16::PC)
+  }
+
+  // Addresses of the last instructions executed, oldest first
+  pub fn history(&self) -> &VecDeque<u16> {
+    &self.history
+  }
+
+  // Run one instruction.  For GBS files, calling PLAY from the idle address
+  // counts as a step.
+  fn step_one(&mut self) -> Result<(), Lockup> {
+    if self.pc() == IDLE_ADDR {
+      if let Some(play) = self.play_addr {
+        self.gb.cpu.call(play);
+        return Ok(());
+      }
+    }
+
+    if self.history.len() == HISTORY_SIZE {
+      self.history.pop_front();
+    }
+    self.history.push_back(self.pc());
+
+    let cycles = self.gb.cpu.step()?;
+    self.cycles += cycles as u64;
+    Ok(())
+  }
+
+  // Step, and check for watchpoints hit by the instruction and breakpoints at
+  // the next one
+  fn step_checked(&mut self) -> Option<Stop> {
+    if let Err(lockup) = self.step_one() {
+      return Some(Stop::Lockup(lockup));
+    }
+
+    if let Some(hit) = self.gb.cpu.bus.as_ref().take_watch_hit() {
+      return Some(Stop::Watchpoint(hit));
+    }
+
+    let pc = self.pc();
+    let gb = &self.gb;
+    self.breakpoints.iter()
+      .position(|b| b.addr == pc && b.condition.is_none_or(|c| c.holds(gb)))
+      .map(Stop::Breakpoint)
+  }
+
+  // Run n instructions
+  pub fn step(&mut self, n: u32) -> Stop {
+    for _ in 0..n {
+      if let Some(stop) = self.step_checked() {
+        return stop;
+      }
+    }
+    Stop::Step
+  }
+
+  // Run until a breakpoint or a watchpoint
+  pub fn cont(&mut self) -> Stop {
+    let end = self.cycles + RUN_LIMIT;
+    while self.cycles < end {
+      if let Some(stop) = self.step_checked() {
+        return stop;
+      }
+    }
+    Stop::Limit
+  }
+
+  // Step over calls and RSTs
+  pub fn step_over(&mut self) -> Stop {
+    let inst = self.instruction(self.pc());
+    if !inst.is_call() {
+      return self.step(1);
+    }
+
+    let sp = self.gb.cpu.rr(R16::SP);
+    let end = self.cycles + RUN_LIMIT;
+    while self.cycles < end {
+      if let Some(stop) = self.step_checked() {
+        return stop;
+      }
+      if self.pc() == inst.next() && self.gb.cpu.rr(R16::SP) >= sp {
+        return Stop::Step;
+      }
+    }
+    Stop::Limit
+  }
+
+  // Run until the current routine returns.  The return addresses of the
+  // calls and interrupts on the way are kept, so that a return to one of them
+  // is theirs; any other return is the one of the routine.
+  pub fn step_out(&mut self) -> Stop {
+    let mut returns = Vec::new();
+    let end = self.cycles + RUN_LIMIT;
+    while self.cycles < end {
+      let pc = self.pc();
+      let mut sp = self.gb.cpu.rr(R16::SP);
+      // An interrupt handler runs first, and returns to pc
+      let addr = match self.gb.cpu.next_interrupt() {
+        Some(vector) => {
+          returns.push(pc);
+          sp = sp.wrapping_sub(2);
+          vector
+        }
+        None => pc,
+      };
+      let inst = self.instruction(addr);
+
+      if let Some(stop) = self.step_checked() {
+        return stop;
+      }
+
+      // Calls and returns not taken leave SP alone
+      let new_sp = self.gb.cpu.rr(R16::SP);
+      if inst.is_call() && new_sp == sp.wrapping_sub(2) {
+        returns.push(inst.next());
+      } else if inst.is_return() && new_sp == sp.wrapping_add(2) {
+        match returns.iter().rposition(|&a| a == self.pc()) {
+          Some(i) => returns.truncate(i),
+          None => return Stop::Step,
+        }
+      }
+    }
+    Stop::Limit
+  }
+
+  pub fn registers(&self) -> String {
+    let cpu = &self.gb.cpu;
+    let f = cpu.r(R8::F);
+    let flag = |mask, c| if f & mask > 0 { c } else { '-' };
+    format!("AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X} \
+             {}{}{}{} IME:{} cycles:{}",
+            cpu.rr(R16::AF), cpu.rr(R16::BC), cpu.rr(R16::DE),
+            cpu.rr(R16::HL), cpu.rr(R16::SP), cpu.rr(R16::PC),
+            flag(0x80, 'Z'), flag(0x40, 'N'), flag(0x20, 'H'), flag(0x10, 'C'),
+            cpu.ime, self.cycles)
+  }
+
+  // Hex dump of len bytes from addr, 16 per line
+  pub fn dump(&self, addr: u16, len: u16) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for i in 0..len {
+      let a = addr.wrapping_add(i);
+      if i % 16 == 0 {
+        if !line.is_empty() {
+          lines.push(line);
+        }
+        line = format!("{:04X}:", a);
+      }
+      line.push_str(&format!(" {:02X}", self.gb.cpu.bus.peek(a)));
+    }
+    if !line.is_empty() {
+      lines.push(line);
+    }
+    lines
+  }
+
+  pub fn instruction(&self, addr: u16) -> Instruction {
+    disassembler::decode_memory(|a| self.gb.cpu.bus.peek(a), addr)
+  }
+
+  // The instruction at addr: address, bytes, mnemonic and location
+  pub fn disassemble(&self, addr: u16) -> (String, u16) {
+    let inst = self.instruction(addr);
+    let bytes = inst.bytes().iter()
+      .map(|b| format!("{:02X}", b))
+      .collect::<Vec<_>>()
+      .join(" ");
+    let symbols = &self.gb.cpu.symbols;
+    let text = inst.format_with(|a| symbols.name(a));
+    let line = match symbols.name(addr) {
+      Some(name) => format!("{:04X}  {:<9} {:<24} ; {}", addr, bytes, text, name),
+      None => format!("{:04X}  {:<9} {}", addr, bytes, text),
+    };
+    (line, inst.length as u16)
+  }
+
+  // The last instructions executed, the current one and the next n-1
+  pub fn disassemble_around_pc(&self, n: u16) -> Vec<String> {
+    let mut lines = Vec::new();
+    for &addr in &self.history {
+      lines.push(format!("   {}", self.disassemble(addr).0));
+    }
+
+    let mut addr = self.pc();
+    for i in 0..n {
+      let (line, len) = self.disassemble(addr);
+      lines.push(format!("{}{}", if i == 0 { "=> " } else { "   " }, line));
+      addr = addr.wrapping_add(len);
+    }
+    lines
+  }
+
+  // The sound and video registers, decoded
+  pub fn io(&self) -> Vec<String> {
+    IO_REGISTERS.iter().map(|&(name, addr)| {
+      let w = self.gb.cpu.bus.peek(addr);
+      format!("{:<5} ${:04X} = ${:02X}  {}", name, addr, w, describe_io(addr, w))
+    }).collect()
+  }
+}
+
+const IO_REGISTERS : [(&str, u16); 33] = [
+  ("NR10", 0xFF10), ("NR11", 0xFF11), ("NR12", 0xFF12), ("NR13", 0xFF13),
+  ("NR14", 0xFF14),
+  ("NR21", 0xFF16), ("NR22", 0xFF17), ("NR23", 0xFF18), ("NR24", 0xFF19),
+  ("NR30", 0xFF1A), ("NR31", 0xFF1B), ("NR32", 0xFF1C), ("NR33", 0xFF1D),
+  ("NR34", 0xFF1E),
+  ("NR41", 0xFF20), ("NR42", 0xFF21), ("NR43", 0xFF22), ("NR44", 0xFF23),
+  ("NR50", 0xFF24), ("NR51", 0xFF25), ("NR52", 0xFF26),
+  ("LCDC", 0xFF40), ("STAT", 0xFF41), ("SCY", 0xFF42), ("SCX", 0xFF43),
+  ("LY", 0xFF44), ("LYC", 0xFF45), ("DMA", 0xFF46), ("BGP", 0xFF47),
+  ("OBP0", 0xFF48), ("OBP1", 0xFF49), ("WY", 0xFF4A), ("WX", 0xFF4B),
+];
+
+fn on(w: u8, bit: u8) -> &'static str {
+  if w & (1 << bit) > 0 { "on" } else { "off" }
+}
+
+fn describe_io(addr: u16, w: u8) -> String {
+  let envelope = |w: u8| format!("volume {} {} period {}", w >> 4,
+                                 if w & 0x08 > 0 { "up" } else { "down" },
+                                 w & 0x07);
+  let palette = |w: u8| format!("{} {} {} {}", w & 3, (w >> 2) & 3,
+                                (w >> 4) & 3, w >> 6);
+
+  match addr {
+    0xFF10 => format!("sweep period {} {} shift {}", (w >> 4) & 7,
+                      if w & 0x08 > 0 { "down" } else { "up" }, w & 7),
+    0xFF11 | 0xFF16 => format!("duty {}", w >> 6),
+    0xFF12 | 0xFF17 | 0xFF21 => envelope(w),
+    0xFF13 | 0xFF18 | 0xFF1D => String::from("frequency low (write-only)"),
+    0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => format!("length {}", on(w, 6)),
+    0xFF1A => format!("DAC {}", on(w, 7)),
+    0xFF1B | 0xFF20 => String::from("length (write-only)"),
+    0xFF1C => format!("volume {}", ["mute", "100%", "50%", "25%"]
+                      [((w >> 5) & 3) as usize]),
+    0xFF22 => format!("shift {} width {} divisor {}", w >> 4,
+                      if w & 0x08 > 0 { 7 } else { 15 }, w & 7),
+    0xFF24 => format!("left {} right {}", (w >> 4) & 7, w & 7),
+    0xFF25 => {
+      let chans = |w: u8| (0..4)
+        .map(|i| if w & (1 << i) > 0 { (b'1' + i) as char } else { '-' })
+        .collect::<String>();
+      format!("left {} right {}", chans(w & 0x0F), chans(w >> 4))
+    }
+    0xFF26 => format!("power {} channels {}{}{}{}", on(w, 7),
+                      if w & 1 > 0 { '1' } else { '-' },
+                      if w & 2 > 0 { '2' } else { '-' },
+                      if w & 4 > 0 { '3' } else { '-' },
+                      if w & 8 > 0 { '4' } else { '-' }),
+    0xFF40 => format!("lcd {} window {} (map {}) tiles {} bg map {} \
+                       sprites {} ({}) bg {}",
+                      on(w, 7), on(w, 5), if w & 0x40 > 0 { "9C00" } else { "9800" },
+                      if w & 0x10 > 0 { "8000" } else { "8800" },
+                      if w & 0x08 > 0 { "9C00" } else { "9800" },
+                      on(w, 1), if w & 0x04 > 0 { "8x16" } else { "8x8" },
+                      on(w, 0)),
+    0xFF41 => format!("mode {} coincidence {}", w & 3, on(w, 2)),
+    0xFF47..=0xFF49 => palette(w),
+    _ => String::new(),
+  }
+}
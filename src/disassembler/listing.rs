@@ -0,0 +1,349 @@
+// Static disassembly of a sound driver: follow the control flow from its entry
+// points to tell code from data, then print an RGBDS listing that reassembles
+// to the same bytes.
+//
+// Only what is mapped at $0000-$7FFF without banking is analysed.  Bytes
+// beyond are printed as data, in banked sections.
+//
+// Jump tables are recognized from the usual `ld hl, Table ... jp hl` pattern.
+// They are read once everything else has been traced, and end at the first
+// word that does not point inside the driver or at code already found.
+//
+// Labels can come from a symbol file.  Those outside of the driver, like
+// variables in RAM, are defined as constants.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use disassembler::{self, Instruction, Mnemonic, Operand};
+use gb::cpu::R16;
+use gbs_parser::Gbs;
+use symbols::Symbols;
+
+const BANK_SIZE : usize = 0x4000;
+const MAPPED_END : usize = 0x8000;
+// Give up on jump tables longer than that
+const MAX_TABLE_ENTRIES : usize = 256;
+const DB_PER_LINE : usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Byte {
+  Unknown,
+  // First byte of an instruction
+  Code,
+  // Following bytes of an instruction
+  Operand,
+  // Entry of a jump table
+  Table,
+}
+
+pub struct Listing {
+  base: u16,
+  data: Vec<u8>,
+  // Where RST vectors point to.  GBS players vector them relative to the
+  // load address.
+  rst_base: u16,
+  kinds: Vec<Byte>,
+  labels: BTreeMap<u16, String>,
+  // Addresses that were loaded in HL before a jp hl
+  tables: BTreeSet<u16>,
+  // Named addresses outside of the data
+  constants: BTreeMap<u16, String>,
+}
+
+impl Listing {
+  // An unanalysed listing of data located at base
+  pub fn new(data: Vec<u8>, base: u16) -> Self {
+    let kinds = vec![Byte::Unknown; data.len()];
+    Listing {
+      base,
+      data,
+      rst_base: 0,
+      kinds,
+      labels: BTreeMap::new(),
+      tables: BTreeSet::new(),
+      constants: BTreeMap::new(),
+    }
+  }
+
+  // Trace a GBS driver from INIT and PLAY, naming what symbols can
+  pub fn from_gbs(gbs: &Gbs, symbols: &Symbols) -> Self {
+    let mut listing = Listing::new(gbs.rom.clone(), gbs.load_addr);
+    listing.rst_base = gbs.load_addr;
+    listing.import_symbols(symbols);
+    listing.trace(gbs.init_addr, "Init");
+    listing.trace(gbs.play_addr, "Play");
+    listing
+  }
+
+  // Name an address, replacing any generated label
+  pub fn set_label(&mut self, addr: u16, name: &str) {
+    if self.contains(addr) {
+      self.labels.insert(addr, name.to_string());
+    }
+  }
+
+  // Use the labels of the mapped banks.  Local labels cannot be constants,
+  // so they are left out when outside of the data.
+  pub fn import_symbols(&mut self, symbols: &Symbols) {
+    let end = self.base as usize + self.data.len();
+    for (addr, name) in symbols.mapped() {
+      if self.contains(addr) {
+        self.set_label(addr, name);
+      } else if ((addr as usize) < self.base as usize || addr as usize >= end)
+        && !name.contains('.') {
+        self.constants.entry(addr).or_insert_with(|| name.to_string());
+      }
+    }
+  }
+
+  // Follow the code from entry, and name it unless it already has a label
+  pub fn trace(&mut self, entry: u16, name: &str) {
+    if self.contains(entry) && !self.labels.contains_key(&entry) {
+      self.set_label(entry, name);
+    }
+
+    let mut work = vec![entry];
+    loop {
+      while let Some(addr) = work.pop() {
+        self.trace_run(addr, &mut work);
+      }
+
+      // Only read jump tables when no other code is left to find, so they do
+      // not run over it
+      let tables = self.tables.iter().cloned().collect::<Vec<_>>();
+      for table in tables {
+        self.read_table(table, &mut work);
+      }
+      if work.is_empty() {
+        break;
+      }
+    }
+  }
+
+  // Size of the analysed part of the data
+  fn mapped_len(&self) -> usize {
+    self.data.len().min(MAPPED_END.saturating_sub(self.base as usize))
+  }
+
+  fn contains(&self, addr: u16) -> bool {
+    addr >= self.base && ((addr - self.base) as usize) < self.mapped_len()
+  }
+
+  fn index(&self, addr: u16) -> usize {
+    (addr - self.base) as usize
+  }
+
+  fn add_label(&mut self, addr: u16, prefix: &str) {
+    if self.contains(addr) {
+      self.labels.entry(addr)
+        .or_insert_with(|| format!("{}_{:04X}", prefix, addr));
+    }
+  }
+
+  // Decode instructions from addr until the flow stops, queueing branch
+  // targets on work
+  fn trace_run(&mut self, mut addr: u16, work: &mut Vec<u16>) {
+    // Last constant loaded in HL, for jump tables
+    let mut hl = None;
+
+    while self.contains(addr) {
+      let i = self.index(addr);
+      let inst = match disassembler::decode(&self.data[i..self.mapped_len()],
+                                            addr) {
+        Some(inst) => inst,
+        None => return,
+      };
+
+      // Stop at code already traced, or at bytes that do not fit an
+      // instruction here
+      if self.kinds[i..i + inst.length as usize].iter()
+        .any(|&k| k != Byte::Unknown) {
+        return;
+      }
+      self.kinds[i] = Byte::Code;
+      for k in 1..inst.length as usize {
+        self.kinds[i + k] = Byte::Operand;
+      }
+
+      if let Some(target) = inst.target {
+        let (target, prefix) = match inst.mnemonic {
+          Mnemonic::Rst => (self.rst_base.wrapping_add(target), "Rst"),
+          Mnemonic::Call => (target, "Sub"),
+          _ => (target, "L"),
+        };
+        if self.contains(target) {
+          self.add_label(target, prefix);
+          work.push(target);
+        }
+      }
+
+      for &op in &inst.operands {
+        match op {
+          Operand::Imm16(nn) | Operand::Addr(nn) => self.add_label(nn, "Data"),
+          _ => {},
+        }
+      }
+
+      match (inst.mnemonic, inst.operands.as_slice()) {
+        (Mnemonic::Ld, &[Operand::Reg16(R16::HL), Operand::Imm16(nn)]) =>
+          hl = Some(nn),
+        (Mnemonic::Jp, &[Operand::Reg16(R16::HL)]) => {
+          if let Some(table) = hl.filter(|&t| self.contains(t)) {
+            self.tables.insert(table);
+          }
+        }
+        _ => {},
+      }
+
+      if inst.ends_flow() {
+        return;
+      }
+      addr = inst.next();
+    }
+  }
+
+  fn read_table(&mut self, table: u16, work: &mut Vec<u16>) {
+    let mut addr = table;
+    for _ in 0..MAX_TABLE_ENTRIES {
+      if !self.contains(addr) || !self.contains(addr.wrapping_add(1)) {
+        break;
+      }
+      let i = self.index(addr);
+      // Already read, or ran into something else
+      if self.kinds[i] != Byte::Unknown || self.kinds[i + 1] != Byte::Unknown
+        || (addr != table && self.labels.contains_key(&addr)) {
+        break;
+      }
+
+      let target = (self.data[i + 1] as u16) << 8 | self.data[i] as u16;
+      if !self.contains(target) {
+        break;
+      }
+
+      self.kinds[i] = Byte::Table;
+      self.kinds[i + 1] = Byte::Table;
+      self.add_label(target, "L");
+      work.push(target);
+      addr = addr.wrapping_add(2);
+    }
+
+    if addr != table {
+      self.labels.entry(table)
+        .and_modify(|l| if l.starts_with("Data_") {
+          *l = format!("Table_{:04X}", table);
+        })
+        .or_insert_with(|| format!("Table_{:04X}", table));
+    }
+  }
+
+  fn label_for(&self, addr: u16) -> Option<String> {
+    self.labels.get(&addr).or_else(|| self.constants.get(&addr)).cloned()
+  }
+
+  // The instruction at index i, if it can be printed as is: it must not hide
+  // a label, nor cross a section, and must reassemble to the same bytes.
+  fn printable_instruction(&self, i: usize) -> Option<Instruction> {
+    let addr = self.base.wrapping_add(i as u16);
+    let inst = disassembler::decode(&self.data[i..self.mapped_len()], addr)?;
+    let len = inst.length as usize;
+
+    let hides_label = (1..len)
+      .any(|k| self.labels.contains_key(&addr.wrapping_add(k as u16)));
+    let crosses = (addr as usize) < BANK_SIZE
+      && addr as usize + len > BANK_SIZE;
+    // RGBDS always pads STOP with a zero
+    let odd_stop = inst.mnemonic == Mnemonic::Stop && inst.bytes()[1] != 0;
+
+    if hides_label || crosses || odd_stop {
+      None
+    } else {
+      Some(inst)
+    }
+  }
+
+  // Start a section for the bytes from index i
+  fn section<W: Write>(&self, out: &mut W, i: usize) -> io::Result<()> {
+    let addr = self.base as usize + i;
+    if addr < BANK_SIZE {
+      writeln!(out, "\nSECTION \"Driver\", ROM0[${:04X}]", addr)
+    } else if addr < MAPPED_END {
+      writeln!(out, "\nSECTION \"Driver bank 1\", ROMX[${:04X}], BANK[1]", addr)
+    } else {
+      let bank = addr / BANK_SIZE;
+      writeln!(out, "\nSECTION \"Driver bank {}\", ROMX[${:04X}], BANK[{}]",
+               bank, BANK_SIZE + addr % BANK_SIZE, bank)
+    }
+  }
+
+  pub fn write_asm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    let mapped = self.mapped_len();
+    let mut i = 0;
+
+    if !self.constants.is_empty() {
+      writeln!(out)?;
+      for (addr, name) in &self.constants {
+        writeln!(out, "{} EQU ${:04X}", name, addr)?;
+      }
+    }
+
+    while i < self.data.len() {
+      let addr = self.base as usize + i;
+      if i == 0 || addr.is_multiple_of(BANK_SIZE) {
+        self.section(out, i)?;
+      }
+
+      if i < mapped {
+        if let Some(label) = self.labels.get(&(addr as u16)) {
+          if self.kinds[i] == Byte::Code {
+            writeln!(out)?;
+          }
+          writeln!(out, "{}:", label)?;
+        }
+        if self.tables.contains(&(addr as u16)) && self.kinds[i] == Byte::Table {
+          writeln!(out, "; jump table")?;
+        }
+      }
+
+      let kind = if i < mapped { self.kinds[i] } else { Byte::Unknown };
+      match kind {
+        Byte::Code => {
+          if let Some(inst) = self.printable_instruction(i) {
+            let text = inst.format_with(|nn| self.label_for(nn));
+            writeln!(out, "\t{:<44}; ${:04X}", text, addr)?;
+            i += inst.length as usize;
+            continue;
+          }
+        }
+
+        Byte::Table if i + 1 < mapped && self.kinds[i + 1] == Byte::Table => {
+          let target = (self.data[i + 1] as u16) << 8 | self.data[i] as u16;
+          let name = self.label_for(target)
+            .unwrap_or_else(|| format!("${:04X}", target));
+          writeln!(out, "\tdw {:<41}; ${:04X}", name, addr)?;
+          i += 2;
+          continue;
+        }
+
+        _ => {},
+      }
+
+      // Data, up to the next label, section or code
+      let start = i;
+      i += 1;
+      while i < self.data.len() && i - start < DB_PER_LINE
+        && !(self.base as usize + i).is_multiple_of(BANK_SIZE)
+        && (i >= mapped || (self.kinds[i] == Byte::Unknown
+                            && !self.labels.contains_key(&(self.base.wrapping_add(i as u16))))) {
+        i += 1;
+      }
+      let bytes = self.data[start..i].iter()
+        .map(|b| format!("${:02X}", b))
+        .collect::<Vec<_>>()
+        .join(", ");
+      writeln!(out, "\tdb {:<41}; ${:04X}", bytes, addr)?;
+    }
+
+    Ok(())
+  }
+}
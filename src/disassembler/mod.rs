@@ -0,0 +1,396 @@
+// Decode SM83 machine code into instructions, and print them in RGBDS syntax.
+//
+// Decoding follows the usual x/y/z split of the opcode (x = bits 7-6, y = bits
+// 5-3, z = bits 2-0), which maps most of the table onto a few patterns.
+// Cycles are in T-cycles, as returned by Cpu::step.
+
+use std::fmt;
+
+use gb::cpu::{R8, R16};
+
+pub mod listing;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mnemonic {
+  Nop, Stop, Halt, Di, Ei,
+  Ld, Ldh, Push, Pop,
+  Inc, Dec, Add, Adc, Sub, Sbc, And, Xor, Or, Cp,
+  Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf,
+  Jr, Jp, Call, Ret, Reti, Rst,
+  Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl, Bit, Res, Set,
+  // Opcodes that lock up the CPU.  Printed as data.
+  Illegal,
+}
+
+impl fmt::Display for Mnemonic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::Mnemonic::*;
+
+    let s = match *self {
+      Nop => "nop", Stop => "stop", Halt => "halt", Di => "di", Ei => "ei",
+      Ld => "ld", Ldh => "ldh", Push => "push", Pop => "pop",
+      Inc => "inc", Dec => "dec", Add => "add", Adc => "adc", Sub => "sub",
+      Sbc => "sbc", And => "and", Xor => "xor", Or => "or", Cp => "cp",
+      Rlca => "rlca", Rrca => "rrca", Rla => "rla", Rra => "rra",
+      Daa => "daa", Cpl => "cpl", Scf => "scf", Ccf => "ccf",
+      Jr => "jr", Jp => "jp", Call => "call", Ret => "ret", Reti => "reti",
+      Rst => "rst",
+      Rlc => "rlc", Rrc => "rrc", Rl => "rl", Rr => "rr", Sla => "sla",
+      Sra => "sra", Swap => "swap", Srl => "srl",
+      Bit => "bit", Res => "res", Set => "set",
+      Illegal => "db",
+    };
+    f.write_str(s)
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cond {
+  NZ, Z, NC, C,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Operand {
+  Reg8(R8),
+  Reg16(R16),
+  Imm8(u8),
+  Imm16(u16),
+  // Memory at the address held by a register: [bc], [de], [hl]
+  Ind(R16),
+  // [hl+] and [hl-]
+  HlInc,
+  HlDec,
+  // Memory at $FF00+C
+  HighC,
+  // Memory at an absolute address: [nn], or [$FF00+n] for LDH
+  Addr(u16),
+  // Destination of a jump, call or RST
+  Target(u16),
+  // SP plus a signed offset, as in ld hl, sp+e
+  SpOffset(i8),
+  // Signed offset added to SP by add sp, e
+  Offset(i8),
+  Cond(Cond),
+  // Bit number of BIT, RES and SET
+  Bit(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+  pub addr: u16,
+  pub mnemonic: Mnemonic,
+  pub operands: Vec<Operand>,
+  // Size in bytes
+  pub length: u8,
+  // T-cycles when a conditional branch is not taken, or always for other
+  // instructions
+  pub cycles: u8,
+  // T-cycles when the branch is taken
+  pub cycles_taken: u8,
+  // Where a jump, call or RST goes, if known statically
+  pub target: Option<u16>,
+  bytes: [u8; 3],
+}
+
+impl Instruction {
+  pub fn bytes(&self) -> &[u8] {
+    &self.bytes[..self.length as usize]
+  }
+
+  // The address of the next instruction in memory
+  pub fn next(&self) -> u16 {
+    self.addr.wrapping_add(self.length as u16)
+  }
+
+  pub fn is_conditional(&self) -> bool {
+    self.operands.iter().any(|o| matches!(o, Operand::Cond(_)))
+  }
+
+  // CALL or RST
+  pub fn is_call(&self) -> bool {
+    matches!(self.mnemonic, Mnemonic::Call | Mnemonic::Rst)
+  }
+
+  // RET or RETI, conditional or not
+  pub fn is_return(&self) -> bool {
+    matches!(self.mnemonic, Mnemonic::Ret | Mnemonic::Reti)
+  }
+
+  // JP or JR, conditional or not
+  pub fn is_jump(&self) -> bool {
+    matches!(self.mnemonic, Mnemonic::Jp | Mnemonic::Jr)
+  }
+
+  // Whether execution never falls through to the next instruction
+  pub fn ends_flow(&self) -> bool {
+    (self.is_jump() || self.is_return()) && !self.is_conditional()
+      || self.mnemonic == Mnemonic::Illegal
+  }
+
+  // The instruction in RGBDS syntax, with targets named by label when it
+  // returns one
+  pub fn format_with<F>(&self, label: F) -> String
+    where F: Fn(u16) -> Option<String> {
+    let operands = self.operands.iter()
+      .map(|&o| format_operand(self.mnemonic, o, &label))
+      .collect::<Vec<_>>();
+
+    if operands.is_empty() {
+      format!("{}", self.mnemonic)
+    } else {
+      format!("{} {}", self.mnemonic, operands.join(", "))
+    }
+  }
+}
+
+// RGBDS syntax
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.format_with(|_| None))
+  }
+}
+
+fn format_operand<F>(mnemonic: Mnemonic, o: Operand, label: &F) -> String
+  where F: Fn(u16) -> Option<String> {
+  match o {
+    Operand::Reg8(r) => format!("{:?}", r).to_lowercase(),
+    Operand::Reg16(rr) => format!("{:?}", rr).to_lowercase(),
+    Operand::Imm8(n) => format!("${:02x}", n),
+    Operand::Imm16(nn) => label(nn).unwrap_or_else(|| format!("${:04x}", nn)),
+    Operand::Ind(rr) => format!("[{:?}]", rr).to_lowercase(),
+    Operand::HlInc => String::from("[hl+]"),
+    Operand::HlDec => String::from("[hl-]"),
+    Operand::HighC => String::from("[c]"),
+    Operand::Addr(nn) => format!("[{}]", label(nn)
+                                 .unwrap_or_else(|| format!("${:04x}", nn))),
+    // RST takes the vector, not a label
+    Operand::Target(nn) if mnemonic == Mnemonic::Rst => format!("${:02x}", nn),
+    Operand::Target(nn) => label(nn).unwrap_or_else(|| format!("${:04x}", nn)),
+    Operand::SpOffset(e) if e < 0 => format!("sp-{}", -(e as i16)),
+    Operand::SpOffset(e) => format!("sp+{}", e),
+    Operand::Offset(e) => format!("{}", e),
+    Operand::Cond(c) => format!("{:?}", c).to_lowercase(),
+    Operand::Bit(b) => format!("{}", b),
+  }
+}
+
+const R : [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::A, R8::A];
+const RP : [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::SP];
+const RP2 : [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::AF];
+const CC : [Cond; 4] = [Cond::NZ, Cond::Z, Cond::NC, Cond::C];
+const ALU : [Mnemonic; 8] = [
+  Mnemonic::Add, Mnemonic::Adc, Mnemonic::Sub, Mnemonic::Sbc,
+  Mnemonic::And, Mnemonic::Xor, Mnemonic::Or, Mnemonic::Cp,
+];
+const ROT : [Mnemonic; 8] = [
+  Mnemonic::Rlc, Mnemonic::Rrc, Mnemonic::Rl, Mnemonic::Rr,
+  Mnemonic::Sla, Mnemonic::Sra, Mnemonic::Swap, Mnemonic::Srl,
+];
+
+// Register operand for index 0-7 of the r table, where 6 is [hl]
+fn r(i: u8) -> Operand {
+  if i == 6 { Operand::Ind(R16::HL) } else { Operand::Reg8(R[i as usize]) }
+}
+
+// Decode the instruction at the start of bytes, located at addr.  None if
+// bytes is too short to hold it.
+pub fn decode(bytes: &[u8], addr: u16) -> Option<Instruction> {
+  let byte = |i: usize| bytes.get(i).cloned().unwrap_or(0);
+  let inst = decode_bytes([byte(0), byte(1), byte(2)], addr);
+  if bytes.len() < inst.length as usize {
+    None
+  } else {
+    Some(inst)
+  }
+}
+
+// Decode the instruction at addr, fetching bytes from read
+pub fn decode_memory<F: Fn(u16) -> u8>(read: F, addr: u16) -> Instruction {
+  decode_bytes([read(addr), read(addr.wrapping_add(1)),
+                read(addr.wrapping_add(2))], addr)
+}
+
+// Decode instructions one after the other until bytes runs out.  Leftover
+// bytes too few for an instruction are ignored.
+pub fn disassemble(bytes: &[u8], addr: u16) -> Vec<Instruction> {
+  let mut insts = Vec::new();
+  let mut offset = 0;
+  while let Some(inst) = decode(&bytes[offset..],
+                                addr.wrapping_add(offset as u16)) {
+    offset += inst.length as usize;
+    insts.push(inst);
+  }
+  insts
+}
+
+fn decode_bytes(bytes: [u8; 3], addr: u16) -> Instruction {
+  use self::Mnemonic::*;
+  use self::Operand::*;
+
+  let op = bytes[0];
+  let n = bytes[1];
+  let nn = (bytes[2] as u16) << 8 | bytes[1] as u16;
+  // Target of a relative jump
+  let rel = addr.wrapping_add(2).wrapping_add(n as i8 as u16);
+
+  let x = op >> 6;
+  let y = (op >> 3) & 7;
+  let z = op & 7;
+  let p = (y >> 1) as usize;
+  let q = y & 1;
+
+  // (mnemonic, operands, length, cycles, cycles when taken)
+  let (mnemonic, operands, length, cycles, taken) : (_, Vec<Operand>, _, _, _) =
+    match (x, z) {
+      (0, 0) => match y {
+        0 => (Nop, vec![], 1, 4, 4),
+        1 => (Ld, vec![Addr(nn), Reg16(R16::SP)], 3, 20, 20),
+        2 => (Stop, vec![], 2, 4, 4),
+        3 => (Jr, vec![Target(rel)], 2, 12, 12),
+        _ => (Jr, vec![Cond(CC[(y - 4) as usize]), Target(rel)], 2, 8, 12),
+      },
+
+      (0, 1) if q == 0 => (Ld, vec![Reg16(RP[p]), Imm16(nn)], 3, 12, 12),
+      (0, 1) => (Add, vec![Reg16(R16::HL), Reg16(RP[p])], 1, 8, 8),
+
+      (0, 2) => {
+        let mem = [Ind(R16::BC), Ind(R16::DE), HlInc, HlDec][p];
+        let a = Reg8(R8::A);
+        let ops = if q == 0 { vec![mem, a] } else { vec![a, mem] };
+        (Ld, ops, 1, 8, 8)
+      }
+
+      (0, 3) => (if q == 0 { Inc } else { Dec }, vec![Reg16(RP[p])], 1, 8, 8),
+
+      (0, 4) | (0, 5) => {
+        let m = if z == 4 { Inc } else { Dec };
+        let c = if y == 6 { 12 } else { 4 };
+        (m, vec![r(y)], 1, c, c)
+      }
+
+      (0, 6) => {
+        let c = if y == 6 { 12 } else { 8 };
+        (Ld, vec![r(y), Imm8(n)], 2, c, c)
+      }
+
+      (0, _) => {
+        let m = [Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf][y as usize];
+        (m, vec![], 1, 4, 4)
+      }
+
+      (1, 6) if y == 6 => (Halt, vec![], 1, 4, 4),
+      (1, _) => {
+        let c = if y == 6 || z == 6 { 8 } else { 4 };
+        (Ld, vec![r(y), r(z)], 1, c, c)
+      }
+
+      (2, _) => {
+        let m = ALU[y as usize];
+        let c = if z == 6 { 8 } else { 4 };
+        (m, alu_operands(m, r(z)), 1, c, c)
+      }
+
+      (_, 0) => match y {
+        0..=3 => (Ret, vec![Cond(CC[y as usize])], 1, 8, 20),
+        4 => (Ldh, vec![Addr(0xFF00 | n as u16), Reg8(R8::A)], 2, 12, 12),
+        5 => (Add, vec![Reg16(R16::SP), Offset(n as i8)], 2, 16, 16),
+        6 => (Ldh, vec![Reg8(R8::A), Addr(0xFF00 | n as u16)], 2, 12, 12),
+        _ => (Ld, vec![Reg16(R16::HL), SpOffset(n as i8)], 2, 12, 12),
+      },
+
+      (_, 1) if q == 0 => (Pop, vec![Reg16(RP2[p])], 1, 12, 12),
+      (_, 1) => match p {
+        0 => (Ret, vec![], 1, 16, 16),
+        1 => (Reti, vec![], 1, 16, 16),
+        2 => (Jp, vec![Reg16(R16::HL)], 1, 4, 4),
+        _ => (Ld, vec![Reg16(R16::SP), Reg16(R16::HL)], 1, 8, 8),
+      },
+
+      (_, 2) => match y {
+        0..=3 => (Jp, vec![Cond(CC[y as usize]), Target(nn)], 3, 12, 16),
+        4 => (Ldh, vec![HighC, Reg8(R8::A)], 1, 8, 8),
+        5 => (Ld, vec![Addr(nn), Reg8(R8::A)], 3, 16, 16),
+        6 => (Ldh, vec![Reg8(R8::A), HighC], 1, 8, 8),
+        _ => (Ld, vec![Reg8(R8::A), Addr(nn)], 3, 16, 16),
+      },
+
+      (_, 3) => match y {
+        0 => (Jp, vec![Target(nn)], 3, 16, 16),
+        1 => return decode_cb(bytes, addr),
+        6 => (Di, vec![], 1, 4, 4),
+        7 => (Ei, vec![], 1, 4, 4),
+        _ => (Illegal, vec![Imm8(op)], 1, 4, 4),
+      },
+
+      (_, 4) if y < 4 =>
+        (Call, vec![Cond(CC[y as usize]), Target(nn)], 3, 12, 24),
+      (_, 4) => (Illegal, vec![Imm8(op)], 1, 4, 4),
+
+      (_, 5) if q == 0 => (Push, vec![Reg16(RP2[p])], 1, 16, 16),
+      (_, 5) if p == 0 => (Call, vec![Target(nn)], 3, 24, 24),
+      (_, 5) => (Illegal, vec![Imm8(op)], 1, 4, 4),
+
+      (_, 6) => {
+        let m = ALU[y as usize];
+        (m, alu_operands(m, Imm8(n)), 2, 8, 8)
+      }
+
+      _ => (Rst, vec![Target(y as u16 * 8)], 1, 16, 16),
+    };
+
+  let target = operands.iter().filter_map(|o| match *o {
+    Target(t) => Some(t),
+    _ => None,
+  }).next();
+
+  Instruction {
+    addr,
+    mnemonic,
+    operands,
+    length,
+    cycles,
+    cycles_taken: taken,
+    target,
+    bytes,
+  }
+}
+
+// ADD, ADC and SBC spell out the A register in RGBDS, the others do not
+fn alu_operands(m: Mnemonic, src: Operand) -> Vec<Operand> {
+  match m {
+    Mnemonic::Add | Mnemonic::Adc | Mnemonic::Sbc =>
+      vec![Operand::Reg8(R8::A), src],
+    _ => vec![src],
+  }
+}
+
+fn decode_cb(bytes: [u8; 3], addr: u16) -> Instruction {
+  let op = bytes[1];
+  let x = op >> 6;
+  let y = (op >> 3) & 7;
+  let z = op & 7;
+
+  let (mnemonic, operands) = match x {
+    0 => (ROT[y as usize], vec![r(z)]),
+    1 => (Mnemonic::Bit, vec![Operand::Bit(y), r(z)]),
+    2 => (Mnemonic::Res, vec![Operand::Bit(y), r(z)]),
+    _ => (Mnemonic::Set, vec![Operand::Bit(y), r(z)]),
+  };
+
+  let cycles = match (x, z) {
+    (1, 6) => 12,
+    (_, 6) => 16,
+    _ => 8,
+  };
+
+  Instruction {
+    addr,
+    mnemonic,
+    operands,
+    length: 2,
+    cycles,
+    cycles_taken: cycles,
+    target: None,
+    bytes,
+  }
+}
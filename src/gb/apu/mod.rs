@@ -1,12 +1,18 @@
+use std::io::{self, Read, Write};
+
 mod flag;
 mod pulse;
 mod wave;
 mod noise;
+pub mod sampler;
 
 use self::flag::Flag;
 use self::pulse::Pulse;
 use self::wave::Wave;
 use self::noise::Noise;
+use gb::state::State;
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 const REGISTERS_MASK : [u8; 23] = [
   0x80, 0x3F, 0x00, 0xFF, 0xBF,
@@ -67,6 +73,10 @@ impl APU {
     use gb::apu::noise::Register::*;
 
     let w = match addr {
+      // Holes between the channels read as all ones
+      0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => return 0xFF,
+      0xFF30..=0xFF3F => return self.wave.read_sample(addr - 0xFF30),
+
       0xFF10 => self.pulse1.read(NR10),
       0xFF11 => self.pulse1.read(NR11),
       0xFF12 => self.pulse1.read(NR12),
@@ -161,10 +171,12 @@ impl APU {
         self.enabled = Flag::from((w & 0x80) > 0);
       }
 
-      0xFF30...0xFF3F => {
+      0xFF30..=0xFF3F => {
         self.wave.write_sample(addr - 0xFF30, w);
       }
 
+      0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => {}
+
       _ => unreachable!()
     }
   }
@@ -176,6 +188,17 @@ impl APU {
     self.wave.clock_frequency();
     self.noise.clock_frequency();
 
+    self.clock_frame_sequencer();
+  }
+
+  // Clock only the low-frequency units (length, envelope, sweep), leaving the
+  // channels where they are in their waveform.  Enough to keep the APU state
+  // right when the output is discarded, and much faster.
+  pub fn step_fast(&mut self) {
+    self.clock_frame_sequencer();
+  }
+
+  fn clock_frame_sequencer(&mut self) {
     // Frame sequencer timing:
     //
     // Step Length Ctr  Vol Env   Sweep
@@ -276,6 +299,34 @@ impl APU {
   }
 }
 
+impl State for APU {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.enabled as u8)?;
+    self.pulse1.save(out)?;
+    self.pulse2.save(out)?;
+    self.wave.save(out)?;
+    self.noise.save(out)?;
+    self.frame_seq.save(out)?;
+    out.write_u8(self.read(0xFF24))?;
+    out.write_u8(self.read(0xFF25))
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.enabled = Flag::from(input.read_u8()?);
+    self.pulse1.load(input)?;
+    self.pulse2.load(input)?;
+    self.wave.load(input)?;
+    self.noise.load(input)?;
+    self.frame_seq.load(input)?;
+    // NR50 and NR51 have no side effect
+    let nr50 = input.read_u8()?;
+    self.write(0xFF24, nr50);
+    let nr51 = input.read_u8()?;
+    self.write(0xFF25, nr51);
+    Ok(())
+  }
+}
+
 // 512Hz timer controlling low-frequency modulation units in the APU
 struct FrameSequencer {
   frame: u32,
@@ -302,3 +353,16 @@ impl FrameSequencer {
     }
   }
 }
+
+impl State for FrameSequencer {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u32_le(self.frame)?;
+    out.write_u16_le(self.period)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.frame = input.read_u32_le()?;
+    self.period = input.read_u16_le()?;
+    Ok(())
+  }
+}
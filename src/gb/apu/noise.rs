@@ -1,5 +1,10 @@
+use std::io::{self, Read, Write};
+
 use gb::apu::flag::Flag;
 use gb::apu::pulse::Sweep;
+use gb::state::{self, State};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 #[derive(Debug)]
 pub enum Register {
@@ -85,7 +90,7 @@ impl Noise {
 
     match reg {
       NR41 => {
-        self.length_counter = 64 - w;
+        self.length_counter = 64 - (w & 0x3F);
       },
 
       NR42 => {
@@ -216,3 +221,41 @@ impl Noise {
     }
   }
 }
+
+impl State for Noise {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.enabled as u8)?;
+    out.write_u8(self.dac_enabled as u8)?;
+    out.write_u32_le(self.period)?;
+    out.write_u8(self.clock_shift)?;
+    out.write_u8(self.width_mode)?;
+    out.write_u8(self.divisor_code)?;
+    out.write_u16_le(self.lfsr)?;
+    out.write_u8(self.length_enabled as u8)?;
+    out.write_u8(self.length_counter)?;
+    out.write_u8(self.volume)?;
+    out.write_u8(self.volume_init)?;
+    out.write_u8(self.volume_counter)?;
+    out.write_u8(self.volume_period)?;
+    out.write_u8(self.volume_sweep as u8)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.enabled = Flag::from(input.read_u8()?);
+    self.dac_enabled = Flag::from(input.read_u8()?);
+    self.period = input.read_u32_le()?;
+    self.clock_shift = input.read_u8()?;
+    self.width_mode = input.read_u8()?;
+    self.divisor_code = input.read_u8()?;
+    self.lfsr = input.read_u16_le()?;
+    self.length_enabled = Flag::from(input.read_u8()?);
+    self.length_counter = input.read_u8()?;
+    self.volume = input.read_u8()?;
+    self.volume_init = input.read_u8()?;
+    self.volume_counter = input.read_u8()?;
+    self.volume_period = input.read_u8()?;
+    self.volume_sweep = Sweep::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("envelope direction"))?;
+    Ok(())
+  }
+}
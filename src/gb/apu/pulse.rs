@@ -1,4 +1,9 @@
+use std::io::{self, Read, Write};
+
 use gb::apu::flag::Flag;
+use gb::state::{self, State};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 #[derive(Debug)]
 pub enum Register {
@@ -141,14 +146,16 @@ impl Pulse {
 
     match reg {
       NR10 => {
-        println!("sweep {}", w);
-        self.sweep_shifts = w & 0x3;
+        if cfg!(feature = "debug") {
+          println!("sweep {}", w);
+        }
+        self.sweep_shifts = w & 0x7;
         self.sweep_direction = match (w >> 3) & 0x1 {
           0 => Sweep::Increase,
           1 => Sweep::Decrease,
           _ => unreachable!(),
         };
-        self.sweep_time = (w >> 6) & 0x3;
+        self.sweep_time = (w >> 4) & 0x7;
       },
 
       NR11 | NR21 => {
@@ -226,7 +233,7 @@ impl Pulse {
   }
 
   pub fn clock_sweep(&mut self) {
-    if self.sweep_time == 0 {
+    if self.sweep_time == 0 || !self.is_enabled() {
       return
     }
 
@@ -236,10 +243,16 @@ impl Pulse {
       self.sweep_counter = self.sweep_time;
       let f = self.frequency;
       let d = f / (1 << self.sweep_shifts);
-      self.frequency = match self.sweep_direction {
+      let f = match self.sweep_direction {
         Sweep::Increase => f + d,
         Sweep::Decrease => f - d,
       };
+      // Sweeping past the highest frequency silences the channel
+      if f > 0x7FF {
+        self.enabled = Flag::Off;
+      } else {
+        self.frequency = f;
+      }
     }
   }
 
@@ -276,3 +289,49 @@ impl Pulse {
     }
   }
 }
+
+impl State for Pulse {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.enabled as u8)?;
+    out.write_u8(self.dac_enabled as u8)?;
+    out.write_u16_le(self.period)?;
+    out.write_u16_le(self.frequency)?;
+    out.write_u8(self.duty as u8)?;
+    out.write_u8(self.duty_idx)?;
+    out.write_u8(self.length_enabled as u8)?;
+    out.write_u8(self.length_counter)?;
+    out.write_u8(self.volume)?;
+    out.write_u8(self.volume_init)?;
+    out.write_u8(self.volume_counter)?;
+    out.write_u8(self.volume_period)?;
+    out.write_u8(self.volume_sweep as u8)?;
+    out.write_u8(self.sweep_shifts)?;
+    out.write_u8(self.sweep_direction as u8)?;
+    out.write_u8(self.sweep_time)?;
+    out.write_u8(self.sweep_counter)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.enabled = Flag::from(input.read_u8()?);
+    self.dac_enabled = Flag::from(input.read_u8()?);
+    self.period = input.read_u16_le()?;
+    self.frequency = input.read_u16_le()?;
+    self.duty = Duty::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("duty"))?;
+    self.duty_idx = input.read_u8()? % 8;
+    self.length_enabled = Flag::from(input.read_u8()?);
+    self.length_counter = input.read_u8()?;
+    self.volume = input.read_u8()?;
+    self.volume_init = input.read_u8()?;
+    self.volume_counter = input.read_u8()?;
+    self.volume_period = input.read_u8()?;
+    self.volume_sweep = Sweep::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("envelope direction"))?;
+    self.sweep_shifts = input.read_u8()? & 0x7;
+    self.sweep_direction = Sweep::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("sweep direction"))?;
+    self.sweep_time = input.read_u8()?;
+    self.sweep_counter = input.read_u8()?;
+    Ok(())
+  }
+}
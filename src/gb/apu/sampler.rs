@@ -0,0 +1,54 @@
+// Downsample the APU output to the host sample rate, as the APU is clocked
+// along with the CPU.
+
+use std::collections::VecDeque;
+
+use gb::GB_FREQ;
+use gb::apu::APU;
+
+pub struct Sampler {
+  rate: u32,
+  // Output a sample every time the accumulator overflows GB_FREQ
+  acc: u32,
+  samples: VecDeque<(f32, f32)>,
+  // Count samples rather than producing them, and clock the APU fast
+  pub skip: bool,
+  skipped: u64,
+}
+
+impl Sampler {
+  pub fn new(rate: u32) -> Self {
+    Sampler {
+      rate,
+      acc: 0,
+      samples: VecDeque::new(),
+      skip: false,
+      skipped: 0,
+    }
+  }
+
+  // Called for every APU cycle
+  pub fn clock(&mut self, apu: &APU) {
+    self.acc += self.rate;
+    if self.acc >= GB_FREQ {
+      self.acc -= GB_FREQ;
+      if self.skip {
+        self.skipped += 1;
+      } else {
+        self.samples.push_back(apu.output());
+      }
+    }
+  }
+
+  // Oldest stereo frame not yet taken
+  pub fn pop(&mut self) -> Option<(f32, f32)> {
+    self.samples.pop_front()
+  }
+
+  // Number of frames skipped since the last call
+  pub fn take_skipped(&mut self) -> u64 {
+    let n = self.skipped;
+    self.skipped = 0;
+    n
+  }
+}
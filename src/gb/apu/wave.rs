@@ -1,4 +1,9 @@
+use std::io::{self, Read, Write};
+
 use gb::apu::flag::Flag;
+use gb::state::{self, State};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 pub enum Register {
   NR30,
@@ -121,6 +126,10 @@ impl Wave {
     }
   }
 
+  pub fn read_sample(&self, idx: u16) -> u8 {
+    self.samples[idx as usize]
+  }
+
   pub fn write_sample(&mut self, idx: u16, w: u8) {
     self.samples[idx as usize] = w;
   }
@@ -202,3 +211,33 @@ impl Wave {
     }
   }
 }
+
+impl State for Wave {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.enabled as u8)?;
+    out.write_u8(self.dac_enabled as u8)?;
+    out.write_u16_le(self.period)?;
+    out.write_u16_le(self.frequency)?;
+    out.write_u8(self.length_enabled as u8)?;
+    out.write_u16_le(self.length_counter)?;
+    out.write_u8(self.volume as u8)?;
+    out.write_all(&self.samples)?;
+    out.write_u8(self.sample_nibble as u8)?;
+    out.write_u8(self.sample_buffer)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.enabled = Flag::from(input.read_u8()?);
+    self.dac_enabled = Flag::from(input.read_u8()?);
+    self.period = input.read_u16_le()?;
+    self.frequency = input.read_u16_le()?;
+    self.length_enabled = Flag::from(input.read_u8()?);
+    self.length_counter = input.read_u16_le()?;
+    self.volume = Volume::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("wave volume"))?;
+    input.read_exact(&mut self.samples)?;
+    self.sample_nibble = input.read_u8()? as usize % 32;
+    self.sample_buffer = input.read_u8()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,73 @@
+// Boot ROMs.  At power on the boot ROM is mapped over the start of the
+// cartridge; it checks the header, then writes to $FF50 to unmap itself for
+// good just before jumping to the cartridge at $0100.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+// DMG, MGB and SGB boot ROMs cover $0000-$00FF
+const DMG_SIZE : usize = 0x100;
+// The CGB boot ROM also covers $0200-$08FF.  The header at $0100-$01FF is the
+// cartridge's, and the file has padding there.
+const CGB_SIZE : usize = 0x900;
+const HEADER_START : u16 = 0x100;
+const HEADER_END : u16 = 0x1FF;
+
+#[derive(Debug)]
+pub enum BootRomError {
+  Io(io::Error),
+  // Neither a DMG nor a CGB boot ROM
+  BadSize(usize),
+}
+
+impl From<io::Error> for BootRomError {
+  fn from(err: io::Error) -> BootRomError {
+    BootRomError::Io(err)
+  }
+}
+
+impl fmt::Display for BootRomError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      BootRomError::Io(ref e) => write!(f, "{}", e),
+      BootRomError::BadSize(n) =>
+        write!(f, "boot ROM is {} bytes, expected {} or {}",
+               n, DMG_SIZE, CGB_SIZE),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct BootRom {
+  data: Vec<u8>,
+}
+
+impl BootRom {
+  pub fn new(data: Vec<u8>) -> Result<Self, BootRomError> {
+    match data.len() {
+      DMG_SIZE | CGB_SIZE => Ok(BootRom { data }),
+      n => Err(BootRomError::BadSize(n)),
+    }
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BootRomError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    BootRom::new(data)
+  }
+
+  // Byte at addr, or None where the cartridge shows through
+  pub fn get(&self, addr: u16) -> Option<u8> {
+    if (HEADER_START..=HEADER_END).contains(&addr) {
+      None
+    } else {
+      self.data.get(addr as usize).cloned()
+    }
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    &self.data
+  }
+}
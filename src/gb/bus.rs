@@ -0,0 +1,69 @@
+// What the CPU sees of the rest of the machine.  The CPU only reads and writes
+// bytes, ticks the bus once per M-cycle, and asks it for interrupts; the
+// memory map and devices behind are up to the bus.
+
+pub trait Bus {
+  // Read a byte of data
+  fn read(&mut self, addr: u16) -> u8;
+
+  fn write(&mut self, addr: u16, w: u8);
+
+  // Read a byte of an instruction
+  fn fetch(&mut self, addr: u16) -> u8 {
+    self.read(addr)
+  }
+
+  // Read without side effects, for traces and debuggers
+  fn peek(&self, addr: u16) -> u8;
+
+  // Advance the rest of the machine by one M-cycle
+  fn tick(&mut self) {}
+
+  // Interrupts both requested and enabled, as a mask of IF bits
+  fn pending_interrupts(&self) -> u8 {
+    0
+  }
+
+  // Clear the request of the interrupt being serviced
+  fn acknowledge_interrupt(&mut self, _mask: u8) {}
+
+  // Address RST n jumps to
+  fn rst_vector(&self, n: u8) -> u16 {
+    n as u16
+  }
+}
+
+const SPACE_SIZE : usize = 0x10000;
+
+// Plain RAM over the whole address space, for testing the CPU alone
+pub struct FlatBus {
+  pub ram: Vec<u8>,
+}
+
+impl Default for FlatBus {
+  fn default() -> Self {
+    FlatBus::new()
+  }
+}
+
+impl FlatBus {
+  pub fn new() -> Self {
+    FlatBus {
+      ram: vec![0; SPACE_SIZE],
+    }
+  }
+}
+
+impl Bus for FlatBus {
+  fn read(&mut self, addr: u16) -> u8 {
+    self.ram[addr as usize]
+  }
+
+  fn write(&mut self, addr: u16, w: u8) {
+    self.ram[addr as usize] = w;
+  }
+
+  fn peek(&self, addr: u16) -> u8 {
+    self.ram[addr as usize]
+  }
+}
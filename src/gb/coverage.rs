@@ -0,0 +1,101 @@
+// Coverage maps: for each byte of the address space, whether the CPU executed
+// it, read it as data, or wrote it.  Instruction fetches, operands included,
+// count as execution and not as reads.  Writes to the ROM area go to the
+// bank controller and are not counted as written.
+//
+// The binary mask holds one byte of flags per byte of memory.  Bytes that are
+// not mapped in the ROM area are flagged UNMAPPED: nothing is known about them.
+
+use std::cell::Cell;
+use std::io::{self, Write};
+
+pub const EXECUTED : u8 = 0x01;
+pub const READ : u8 = 0x02;
+pub const WRITTEN : u8 = 0x04;
+pub const UNMAPPED : u8 = 0x80;
+
+const SPACE_SIZE : usize = 0x10000;
+const ROM_END : usize = 0x8000;
+
+pub struct Coverage {
+  // Cells since reads do not borrow the hardware mutably
+  flags: Vec<Cell<u8>>,
+}
+
+impl Default for Coverage {
+  fn default() -> Self {
+    Coverage::new()
+  }
+}
+
+impl Coverage {
+  pub fn new() -> Self {
+    Coverage {
+      flags: vec![Cell::new(0); SPACE_SIZE],
+    }
+  }
+
+  pub fn mark(&self, addr: u16, flag: u8) {
+    let cell = &self.flags[addr as usize];
+    cell.set(cell.get() | flag);
+  }
+
+  pub fn get(&self, addr: u16) -> u8 {
+    self.flags[addr as usize].get()
+  }
+
+  // Flags of len bytes of ROM from start.  Bytes past $7FFF were never mapped.
+  pub fn mask(&self, start: u16, len: usize) -> Vec<u8> {
+    (start as usize..start as usize + len)
+      .map(|a| if a < ROM_END { self.flags[a].get() } else { UNMAPPED })
+      .collect()
+  }
+
+  pub fn write_mask<W: Write>(&self, out: &mut W, start: u16, len: usize)
+                              -> io::Result<()> {
+    out.write_all(&self.mask(start, len))
+  }
+
+  // Byte counts, then the ranges of bytes with the same flags
+  pub fn write_summary<W: Write>(&self, out: &mut W, start: u16, len: usize)
+                                 -> io::Result<()> {
+    let mask = self.mask(start, len);
+    let count = |flag: u8| mask.iter().filter(|&&f| f & flag > 0).count();
+    let untouched = mask.iter().filter(|&&f| f == 0).count();
+    let unmapped = mask.iter().filter(|&&f| f == UNMAPPED).count();
+
+    writeln!(out, "Executed:  {:>6} bytes", count(EXECUTED))?;
+    writeln!(out, "Read:      {:>6} bytes", count(READ))?;
+    writeln!(out, "Written:   {:>6} bytes", count(WRITTEN))?;
+    writeln!(out, "Untouched: {:>6} of {} bytes", untouched, len)?;
+    if unmapped > 0 {
+      writeln!(out, "Unmapped:  {:>6} bytes past $7FFF, unknown", unmapped)?;
+    }
+    writeln!(out)?;
+
+    let mut i = 0;
+    while i < mask.len() {
+      let flags = mask[i];
+      let run = mask[i..].iter().take_while(|&&f| f == flags).count();
+      let first = start as usize + i;
+      writeln!(out, "${:04X}-${:04X} {:>6} bytes  {}", first, first + run - 1,
+               run, describe(flags))?;
+      i += run;
+    }
+    Ok(())
+  }
+}
+
+fn describe(flags: u8) -> String {
+  if flags == 0 {
+    return String::from("untouched");
+  }
+  if flags == UNMAPPED {
+    return String::from("unmapped, unknown");
+  }
+  [(EXECUTED, "executed"), (READ, "read"), (WRITTEN, "written")].iter()
+    .filter(|&&(f, _)| flags & f > 0)
+    .map(|&(_, name)| name)
+    .collect::<Vec<_>>()
+    .join(", ")
+}
@@ -2,9 +2,10 @@ use gb::cpu::registers::{R8, R16, FLAG};
 use gb::cpu::registers::R8::{A, C};
 use gb::cpu::registers::R16::{HL, SP, PC};
 use gb::cpu::registers::FLAG::{Z, N, H as HY, C as CY};
-use gb::cpu::cpu::Cpu;
+use gb::bus::Bus;
+use gb::cpu::cpu::{Cpu, Lockup};
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
 
   // 8bit loads
 
@@ -157,10 +158,8 @@ impl Cpu {
   }
 
   pub fn op_push_rr(&mut self, rr: R16) -> u8 {
-    let sp = self.rr(SP).wrapping_sub(2);
-    self.rr_set(SP, sp);
     let v = self.rr(rr);
-    self.write_16le(sp, v);
+    self.push(v);
     16
   }
 
@@ -180,7 +179,7 @@ impl Cpu {
     let vh = (a as u16) + (x as u16);
     let vl = (a & 0x0F) + (x & 0x0F);
     self.r_set(A, vh as u8);
-    self.f_setb(Z, vh == 0);
+    self.f_setb(Z, vh as u8 == 0);
     self.f_clear(N);
     self.f_setb(HY, vl > 0x0F);
     self.f_setb(CY, vh > 0xFF);
@@ -211,7 +210,7 @@ impl Cpu {
     let vh = (a as u16) + (x as u16) + (c as u16);
     let vl = (a & 0x0F) + (x & 0x0F) + c;
     self.r_set(A, vh as u8);
-    self.f_setb(Z, vh == 0);
+    self.f_setb(Z, vh as u8 == 0);
     self.f_clear(N);
     self.f_setb(HY, vl > 0x0F);
     self.f_setb(CY, vh > 0xFF);
@@ -240,7 +239,7 @@ impl Cpu {
     let a = self.r(A);
     let vh = (a as u16).wrapping_sub(x as u16);
     let vl = (a & 0x0F).wrapping_sub(x & 0x0F);
-    self.f_setb(Z, vh == 0);
+    self.f_setb(Z, vh as u8 == 0);
     self.f_set(N);
     self.f_setb(HY, vl > 0x0F);
     self.f_setb(CY, vh > 0xFF);
@@ -275,7 +274,7 @@ impl Cpu {
     let vh = (a as u16).wrapping_sub(x as u16).wrapping_sub(c as u16);
     let vl = (a & 0x0F).wrapping_sub(x & 0x0F).wrapping_sub(c);
     self.r_set(A, vh as u8);
-    self.f_setb(Z, vh == 0);
+    self.f_setb(Z, vh as u8 == 0);
     self.f_set(N);
     self.f_setb(HY, vl > 0x0F);
     self.f_setb(CY, vh > 0xFF);
@@ -393,7 +392,7 @@ impl Cpu {
   pub fn op_cp_n(&mut self) -> u8 {
     let x = self.read_pc();
     self._sub(x);
-    4
+    8
   }
 
   pub fn op_cp_hl(&mut self) -> u8 {
@@ -452,7 +451,7 @@ impl Cpu {
   pub fn op_daa(&mut self) -> u8 {
     // Code lifted from Higan, because specs are glossing over this one
     let mut a = self.r(A) as u16;
-    if self.f(N) {
+    if !self.f(N) {
       if self.f(HY) || ((a & 0x0F) > 0x09) { a = a.wrapping_add(0x06) }
       if self.f(CY) || ((a       ) > 0x9F) { a = a.wrapping_add(0x60) }
     } else {
@@ -460,12 +459,13 @@ impl Cpu {
         a = a.wrapping_sub(0x06);
         if !self.f(CY) { a &= 0xFF }
       }
-      if self.f(CY) { a = a.wrapping_sub(0xFF) }
+      if self.f(CY) { a = a.wrapping_sub(0x60) }
     }
     self.r_set(A, a as u8);
-    self.f_setb(Z, a == 0);
+    self.f_setb(Z, a as u8 == 0);
     self.f_clear(HY);
-    self.f_setb(CY, a & 0x100 > 0);
+    // Carry is only ever set, never cleared, by the adjustment
+    if a & 0x100 > 0 { self.f_set(CY) }
     4
   }
 
@@ -540,7 +540,7 @@ impl Cpu {
     let v = x.rotate_right(1);
     self.f_clear(N);
     self.f_clear(HY);
-    self.f_setb(CY, v & 0b1000_000 > 0);
+    self.f_setb(CY, v & 0b1000_0000 > 0);
     v
   }
 
@@ -826,6 +826,14 @@ impl Cpu {
     4
   }
 
+  // PC stays on the opcode
+  pub fn op_illegal(&mut self, opcode: u8) -> u8 {
+    let addr = self.rr(PC).wrapping_sub(1);
+    self.rr_set(PC, addr);
+    self.lockup = Some(Lockup { addr, opcode });
+    4
+  }
+
   pub fn op_di(&mut self) -> u8 {
     self.ime = 0;
     4
@@ -884,10 +892,8 @@ impl Cpu {
 
   pub fn op_call_nn(&mut self) -> u8 {
     let nn = self.read_pc_16le();
-    let sp = self.rr(SP).wrapping_sub(2);
     let pc = self.rr(PC);
-    self.rr_set(SP, sp);
-    self.write_16le(sp, pc);
+    self.push(pc);
     self.rr_set(PC, nn);
     24
   }
@@ -896,10 +902,8 @@ impl Cpu {
     let nn = self.read_pc_16le();
     let mut cycles = 12;
     if self.f(f) == b {
-      let sp = self.rr(SP).wrapping_sub(2);
       let pc = self.rr(PC);
-      self.rr_set(SP, sp);
-      self.write_16le(sp, pc);
+      self.push(pc);
       self.rr_set(PC, nn);
       cycles += 12;
     }
@@ -915,6 +919,8 @@ impl Cpu {
   }
 
   pub fn op_ret_f(&mut self, f: FLAG, b: bool) -> u8 {
+    // Checking the condition takes a cycle
+    self.internal_cycle();
     let mut cycles = 8;
     if self.f(f) == b {
       let sp = self.rr(SP);
@@ -932,11 +938,9 @@ impl Cpu {
   }
 
   pub fn op_rst(&mut self, n: u8) -> u8 {
-    let sp = self.rr(SP).wrapping_sub(2);
     let pc = self.rr(PC);
-    self.rr_set(SP, sp);
-    self.write_16le(sp, pc);
-    let addr = self.rst_offset + (n as u16);
+    self.push(pc);
+    let addr = self.bus.rst_vector(n);
     self.rr_set(PC, addr);
     16
   }
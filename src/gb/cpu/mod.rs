@@ -2,6 +2,6 @@ mod registers;
 mod instructions;
 mod cpu;
 
-pub use self::cpu::Cpu;
+pub use self::cpu::{Cpu, Lockup};
 pub use self::registers::R8;
 pub use self::registers::R16;
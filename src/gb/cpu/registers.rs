@@ -1,16 +1,21 @@
+use std::io::{self, Read, Write};
+
+use gb::state::State;
 use gb::utils::{to_u16, from_u16};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 const Z_FLAG : u8 = 0b1000_0000;
 const N_FLAG : u8 = 0b0100_0000;
 const H_FLAG : u8 = 0b0010_0000;
 const C_FLAG : u8 = 0b0001_0000;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum R8 {
   A, B, C, D, E, F, H, L
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum R16 {
   AF, BC, DE, HL, SP, PC,
 }
@@ -128,3 +133,29 @@ impl Registers {
     if b { self.f_set(f); } else { self.f_clear(f); }
   }
 }
+
+impl State for Registers {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_all(&[self.a, self.f, self.b, self.c,
+                    self.d, self.e, self.h, self.l])?;
+    out.write_u16_le(self.sp)?;
+    out.write_u16_le(self.pc)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    let mut r = [0; 8];
+    input.read_exact(&mut r)?;
+    self.a = r[0];
+    // The low nibble of F is always zero
+    self.f = r[1] & 0xF0;
+    self.b = r[2];
+    self.c = r[3];
+    self.d = r[4];
+    self.e = r[5];
+    self.h = r[6];
+    self.l = r[7];
+    self.sp = input.read_u16_le()?;
+    self.pc = input.read_u16_le()?;
+    Ok(())
+  }
+}
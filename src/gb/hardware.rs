@@ -1,111 +1,300 @@
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+
 use gb::lcd::LCD;
 use gb::apu::APU;
+use gb::apu::sampler::Sampler;
+use gb::boot_rom::BootRom;
+use gb::bus::Bus;
+use gb::coverage::{self, Coverage};
+use gb::model::Model;
+use gb::serial::Serial;
+use gb::state::{self, State};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 const RAM_SIZE : usize = 0x10000;
 
+// Interrupt requests and enable
+const IF : usize = 0xFF0F;
+const IE : usize = 0xFFFF;
+const INTERRUPTS : u8 = 0x1F;
+
+// Any write unmaps the boot ROM
+const BOOT : u16 = 0xFF50;
+
+const LY : u16 = 0xFF44;
+// What Gameboy Doctor logs expect LY to read
+const DOCTOR_LY : u8 = 0x90;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+  Read,
+  Write,
+}
+
+// Stop the debugger when the CPU accesses memory in [start,end]
+#[derive(Debug, Copy, Clone)]
+pub struct Watchpoint {
+  pub start: u16,
+  pub end: u16,
+  pub read: bool,
+  pub write: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WatchHit {
+  pub addr: u16,
+  pub access: Access,
+  pub value: u8,
+}
+
 pub struct Hardware {
   pub ram: [u8; RAM_SIZE],
+  // For the few reads that differ between models
+  pub model: Model,
+  // Mapped over the cartridge until a write to BOOT
+  boot_rom: Option<BootRom>,
   lcd: LCD,
   apu: APU,
+
+  pub watchpoints: Vec<Watchpoint>,
+  // First watchpoint hit since the last take_watch_hit.  A Cell since reads
+  // do not borrow mutably.
+  watch_hit: Cell<Option<WatchHit>>,
+
+  pub coverage: Option<Coverage>,
+
+  pub serial: Serial,
+
+  // LY always reads DOCTOR_LY, to compare traces with Gameboy Doctor logs
+  pub doctor_ly: bool,
+
+  // Takes the APU output for the player, if any
+  pub sampler: Option<Sampler>,
 }
 
 impl Hardware {
   pub fn new() -> Hardware {
     Hardware {
       ram: [0; RAM_SIZE],
+      model: Model::Dmg,
+      boot_rom: None,
       lcd: LCD::new(),
       apu: APU::new(),
+      watchpoints: Vec::new(),
+      watch_hit: Cell::new(None),
+      coverage: None,
+      serial: Serial::new(),
+      doctor_ly: false,
+      sampler: None,
     }
   }
 }
 
-impl Hardware {
-  pub fn read(&self, addr: u16) -> u8 {
+impl Bus for Hardware {
+  fn read(&mut self, addr: u16) -> u8 {
+    let w = self.peek(addr);
+    if !self.watchpoints.is_empty() {
+      self.watch(addr, Access::Read, w);
+    }
+    if let Some(ref c) = self.coverage {
+      c.mark(addr, coverage::READ);
+    }
+    w
+  }
+
+  fn fetch(&mut self, addr: u16) -> u8 {
+    let w = self.peek(addr);
+    if !self.watchpoints.is_empty() {
+      self.watch(addr, Access::Read, w);
+    }
+    if let Some(ref c) = self.coverage {
+      c.mark(addr, coverage::EXECUTED);
+    }
+    w
+  }
+
+  // Read without triggering watchpoints, for the debugger itself
+  fn peek(&self, addr: u16) -> u8 {
+    if let Some(w) = self.boot_rom.as_ref().and_then(|b| b.get(addr)) {
+      return w;
+    }
+
     match addr {
-      0xE000...0xFDFF => self.read(addr - 0x2000),
-      0xFF10...0xFF3F => self.apu.read(addr),
-      0xFF40 => self.lcd.read(addr),
-      0xFF42 => self.lcd.read(addr),
-      0xFF43 => self.lcd.read(addr),
-      0xFF44 => self.lcd.read(addr),
-      0xFF47 => self.lcd.read(addr),
+      0x8000..=0x9FFF if !self.lcd.vram_accessible() => 0xFF,
+      0xE000..=0xFDFF => self.peek(addr - 0x2000),
+      0xFE00..=0xFE9F if !self.lcd.oam_accessible() => 0xFF,
+      0xFEA0..=0xFEFF => self.unusable(addr),
+      0xFF01 | 0xFF02 => self.serial.read(addr),
+      0xFF10..=0xFF3F => self.apu.read(addr),
+      LY if self.doctor_ly => DOCTOR_LY,
+      0xFF40..=0xFF45 | 0xFF47 => self.lcd.read(addr),
+      0xFF00..=0xFF7F => self.ram[addr as usize] | io_mask(addr),
       _ => self.ram[addr as usize]
     }
   }
 
-  pub fn write(&mut self, addr: u16, w: u8) {
-    if cfg!(feature = "debug") {
-      match addr {
-        0xFF01 => println!("{:x} {}", w, ASCII[w as usize]),
-        0xFF50 => println!("bingo"),
-        _ => {},
+  fn write(&mut self, addr: u16, w: u8) {
+    if !self.watchpoints.is_empty() {
+      self.watch(addr, Access::Write, w);
+    }
+    // ROM writes only reach the bank controller
+    if let Some(ref c) = self.coverage {
+      if addr >= 0x8000 {
+        c.mark(addr, coverage::WRITTEN);
       }
     }
 
     match addr {
-      0xE000...0xFDFF => self.write(addr - 0x2000, w),
-      0xFF10...0xFF3F => self.apu.write(addr, w),
-      0xFF40 => self.lcd.write(addr, w),
-      0xFF42 => self.lcd.write(addr, w),
-      0xFF43 => self.lcd.write(addr, w),
-      0xFF44 => self.lcd.write(addr, w),
-      0xFF47 => self.lcd.write(addr, w),
+      0x8000..=0x9FFF if !self.lcd.vram_accessible() => {},
+      0xE000..=0xFDFF => self.write(addr - 0x2000, w),
+      0xFE00..=0xFE9F if !self.lcd.oam_accessible() => {},
+      0xFEA0..=0xFEFF => {},
+      0xFF10..=0xFF3F => self.apu.write(addr, w),
+      0xFF40..=0xFF45 | 0xFF47 => self.lcd.write(addr, w),
+      0xFF01 | 0xFF02 => self.serial.write(addr, w),
+      BOOT => {
+        self.boot_rom = None;
+        self.ram[addr as usize] = w;
+      }
       _ => self.ram[addr as usize] = w
     }
   }
 
-  pub fn apu_step(&mut self) {
-    self.apu.step();
+  // 4 T-cycles, so that the CPU memory accesses happen at the right time
+  // relative to the APU
+  fn tick(&mut self) {
+    for _ in 0..4 {
+      self.ram[IF] |= self.lcd.step() | self.serial.step();
+
+      match self.sampler {
+        Some(ref mut s) => {
+          if s.skip {
+            self.apu.step_fast();
+          } else {
+            self.apu.step();
+          }
+          s.clock(&self.apu);
+        }
+        None => self.apu.step(),
+      }
+    }
+  }
+
+  fn pending_interrupts(&self) -> u8 {
+    self.ram[IF] & self.ram[IE] & INTERRUPTS
   }
 
-  pub fn apu_output(&self) -> (f32, f32) {
-    self.apu.output()
+  fn acknowledge_interrupt(&mut self, mask: u8) {
+    self.ram[IF] &= !mask;
   }
 }
 
-// For running ROM tests
-const ASCII : [char; 256] = [
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ',
-
-  ' ', '!', '"', '$', '%', '&', '\'',
-  '(', ')', '*', '+', ',', '-', '.',
-  '/', '0', '1', '2', '3', '4', '5',
-  '6', '7', '8', '9', ':', ';', '<',
-  '=', '>', '?', '@', 'A', 'B', 'C',
-  'D', 'E', 'F', 'G', 'H', 'I', 'J',
-  'K', 'L', 'M', 'N', 'O', 'P', 'Q',
-  'R', 'S', 'T', 'U', 'V', 'W', 'X',
-  'Y', 'Z', '[', '\\', ']', '^', '_',
-  '`', 'a', 'b', 'c', 'd', 'e', 'f',
-  'g', 'h', 'i', 'j', 'k', 'l', 'm',
-  'n', 'o', 'p', 'q', 'r', 's', 't',
-  'u', 'v', 'w', 'x', 'y', 'z', '{',
-  '}', '~', ' ',
-
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-  ' ', ' ', ' ', ' ',
-
-  ' ', '!', '"', '$', '%', '&', '\'',
-  '(', ')', '*', '+', ',', '-', '.',
-  '/', '0', '1', '2', '3', '4', '5',
-  '6', '7', '8', '9', ':', ';', '<',
-  '=', '>', '?', '@', 'A', 'B', 'C',
-  'D', 'E', 'F', 'G', 'H', 'I', 'J',
-  'K', 'L', 'M', 'N', 'O', 'P', 'Q',
-  'R', 'S', 'T', 'U', 'V', 'W', 'X',
-  'Y', 'Z', '[', '\\', ']', '^', '_',
-  '`', 'a', 'b', 'c', 'd', 'e', 'f',
-  'g', 'h', 'i', 'j', 'k', 'l', 'm',
-  'n', 'o', 'p', 'q', 'r', 's', 't',
-  'u', 'v', 'w', 'x', 'y', 'z', '{',
-  '}', '~', ' ',
-
-  ' ', ' ', ' ', ' '
-];
+impl Hardware {
+  // $FEA0-$FEFF
+  fn unusable(&self, addr: u16) -> u8 {
+    match self.model {
+      _ if !self.lcd.oam_accessible() => 0xFF,
+      Model::Dmg | Model::Mgb | Model::Sgb => 0x00,
+      // The high nibble of the low address byte, twice (CGB revision E)
+      Model::Cgb => {
+        let n = (addr as u8) >> 4;
+        n << 4 | n
+      }
+    }
+  }
+
+  // Map boot_rom over the cartridge, as at power on
+  pub fn map_boot_rom(&mut self, boot_rom: BootRom) {
+    self.boot_rom = Some(boot_rom);
+  }
+
+  pub fn boot_rom_mapped(&self) -> bool {
+    self.boot_rom.is_some()
+  }
+
+  // Bytes sent on the serial port since power on
+  pub fn serial_output(&self) -> &[u8] {
+    self.serial.output()
+  }
+
+  fn watch(&self, addr: u16, access: Access, value: u8) {
+    if self.watch_hit.get().is_some() {
+      return;
+    }
+
+    let hit = self.watchpoints.iter().any(|wp| {
+      addr >= wp.start && addr <= wp.end
+        && match access {
+          Access::Read => wp.read,
+          Access::Write => wp.write,
+        }
+    });
+
+    if hit {
+      self.watch_hit.set(Some(WatchHit { addr, access, value }));
+    }
+  }
+
+  pub fn take_watch_hit(&self) -> Option<WatchHit> {
+    self.watch_hit.take()
+  }
+}
+
+// Bits of I/O registers that always read as 1.  Unmapped registers read $FF.
+// The serial port, sound and video registers handle their own.
+fn io_mask(addr: u16) -> u8 {
+  match addr {
+    // No button is ever pressed
+    0xFF00 => 0xCF,
+    0xFF04..=0xFF06 => 0x00,
+    0xFF07 => 0xF8,
+    0xFF0F => 0xE0,
+    0xFF46 => 0x00,
+    0xFF48..=0xFF4B => 0x00,
+    _ => 0xFF,
+  }
+}
+
+impl AsRef<Hardware> for Hardware {
+  fn as_ref(&self) -> &Hardware {
+    self
+  }
+}
+
+impl AsMut<Hardware> for Hardware {
+  fn as_mut(&mut self) -> &mut Hardware {
+    self
+  }
+}
+
+impl State for Hardware {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.model as u8)?;
+    out.write_all(&self.ram)?;
+    self.lcd.save(out)?;
+    self.apu.save(out)?;
+    // The boot ROM, if still mapped, with its length
+    let boot = self.boot_rom.as_ref().map_or(&[][..], |b| b.bytes());
+    out.write_u16_le(boot.len() as u16)?;
+    out.write_all(boot)?;
+    self.serial.save(out)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.model = Model::from_u8(input.read_u8()?)
+      .ok_or_else(|| state::invalid_data("model"))?;
+    input.read_exact(&mut self.ram)?;
+    self.lcd.load(input)?;
+    self.apu.load(input)?;
+    let mut boot = vec![0; input.read_u16_le()? as usize];
+    input.read_exact(&mut boot)?;
+    self.boot_rom = if boot.is_empty() {
+      None
+    } else {
+      Some(BootRom::new(boot).map_err(|_| state::invalid_data("boot ROM"))?)
+    };
+    self.serial.load(input)
+  }
+}
@@ -1,4 +1,9 @@
+use std::io::{self, Read, Write};
+
 use super::super::screen::Screen;
+use gb::state::{self, State};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 // The internal screen of the gameboy is 256*256
 const SCREEN_HEIGHT: usize = 256;
@@ -11,6 +16,25 @@ const LCD_WIDTH: usize = 144;
 const TILE_MAP_SIZE: usize = 0x400;
 const TILE_DATA_SIZE: usize = 0x1000;
 
+// Each line takes 456 dots: OAM scan (mode 2), then pixel transfer (mode 3),
+// then h-blank (mode 0).  Mode 3 is really longer with sprites, window and
+// fine scrolling.  Lines 144 to 153 are the v-blank (mode 1).
+const LINE_DOTS: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const TRANSFER_DOTS: u16 = 172;
+const VBLANK_LINE: u8 = 144;
+const LINES: u8 = 154;
+
+// Interrupt requests, as IF bits
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const STAT_INTERRUPT: u8 = 0x02;
+
+// STAT interrupt sources
+const STAT_HBLANK: u8 = 0x08;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_OAM: u8 = 0x20;
+const STAT_LYC: u8 = 0x40;
+
 // The LCD screen, as part of the GameBoy API.  Holds the logical screen of four
 // shades, and all the video-related registers.
 pub struct LCD {
@@ -18,19 +42,19 @@ pub struct LCD {
 
   control: Control,
 
-  // Status register (STAT)
-  // ly_coincidence_interrupt: bool,
-  // mode2_oam_interrupt: bool,
-  // mode1_vblank_interrupt: bool,
-  // mode0_hblank_interrupt: bool,
-  // coincidence_flag: bool,
-  // mode_flag: Mode,
+  // Status register (STAT): the interrupt sources enabled, as STAT bits
+  stat_sources: u8,
+  mode: Mode,
+  // Dot in the current line
+  dot: u16,
+  // The STAT interrupt is requested when any enabled source goes up
+  stat_line: bool,
 
   // Position and scrolling
   pub scroll_y: u8,
   pub scroll_x: u8,
   y_coordinate: u8,
-  // y_compare: u8,
+  y_compare: u8,
   // window_y: u8,
   // window_x: u8,
 
@@ -191,7 +215,8 @@ impl From<Palette> for u8 {
   }
 }
 
-#[derive(Copy, Clone)]
+// In the order of the STAT mode bits
+#[derive(Copy, Clone, PartialEq)]
 enum Mode {
   HBlank,
   VBlank,
@@ -206,9 +231,15 @@ impl LCD {
 
       control: Control::new(),
 
+      stat_sources: 0,
+      mode: Mode::HBlank,
+      dot: 0,
+      stat_line: false,
+
       scroll_y: 0,
       scroll_x: 0,
       y_coordinate: 0,
+      y_compare: 0,
 
       bg_palette: Palette::new(),
 
@@ -222,9 +253,13 @@ impl LCD {
   pub fn read(&self, addr: u16) -> u8 {
     match addr {
       0xFF40 => (&self.control).into(),
+      0xFF41 => 0x80 | self.stat_sources
+        | (self.coincidence() as u8) << 2
+        | self.mode as u8,
       0xFF42 => self.scroll_x,
       0xFF43 => self.scroll_y,
       0xFF44 => self.y_coordinate,
+      0xFF45 => self.y_compare,
       0xFF47 => self.bg_palette.into(),
       _ => unreachable!(),
     }
@@ -232,21 +267,90 @@ impl LCD {
 
   pub fn write(&mut self, addr: u16, w: u8) {
     match addr {
-      0xFF40 => self.control = w.into(),
+      0xFF40 => {
+        self.control = w.into();
+        // Switching the LCD off resets it to the start of the frame
+        if !self.control.lcd_enable {
+          self.y_coordinate = 0;
+          self.dot = 0;
+          self.mode = Mode::HBlank;
+        }
+      }
+      0xFF41 => self.stat_sources = w & 0x78,
       0xFF42 => self.scroll_x = w,
       0xFF43 => self.scroll_y = w,
-      0xFF44 => self.y_coordinate = w,
+      // LY is read-only
+      0xFF44 => {}
+      0xFF45 => self.y_compare = w,
       0xFF47 => self.bg_palette = w.into(),
       _ => unreachable!(),
     }
 
-    match addr {
-      0xFF40 => {
-        println!("Wrote {:x} to LCDC", w);
-        println!("{:?}", self.control);
-      }
-      _ => ()
+    if cfg!(feature = "debug") && addr == 0xFF40 {
+      println!("Wrote {:x} to LCDC", w);
+      println!("{:?}", self.control);
+    }
+  }
+
+  // Advance by one dot.  Return the interrupts requested, as IF bits.
+  pub fn step(&mut self) -> u8 {
+    if !self.control.lcd_enable {
+      return 0;
+    }
+
+    self.dot += 1;
+    if self.dot == LINE_DOTS {
+      self.dot = 0;
+      self.y_coordinate = (self.y_coordinate + 1) % LINES;
+    }
+
+    let mut interrupts = 0;
+
+    self.mode = if self.y_coordinate >= VBLANK_LINE {
+      Mode::VBlank
+    } else if self.dot < OAM_SCAN_DOTS {
+      Mode::Reading_OAM
+    } else if self.dot < OAM_SCAN_DOTS + TRANSFER_DOTS {
+      Mode::Reading_OAM_and_VRAM
+    } else {
+      Mode::HBlank
+    };
+
+    if self.y_coordinate == VBLANK_LINE && self.dot == 0 {
+      interrupts |= VBLANK_INTERRUPT;
+    }
+
+    let line = self.stat_sources_up();
+    if line && !self.stat_line {
+      interrupts |= STAT_INTERRUPT;
+    }
+    self.stat_line = line;
+
+    interrupts
+  }
+
+  fn coincidence(&self) -> bool {
+    self.y_coordinate == self.y_compare
+  }
+
+  fn stat_sources_up(&self) -> bool {
+    let mode_source = match self.mode {
+      Mode::HBlank => STAT_HBLANK,
+      Mode::VBlank => STAT_VBLANK,
+      Mode::Reading_OAM => STAT_OAM,
+      Mode::Reading_OAM_and_VRAM => 0,
     };
+    self.stat_sources & mode_source > 0
+      || (self.stat_sources & STAT_LYC > 0 && self.coincidence())
+  }
+
+  // The CPU cannot access OAM during modes 2 and 3, nor VRAM during mode 3
+  pub fn oam_accessible(&self) -> bool {
+    self.mode == Mode::HBlank || self.mode == Mode::VBlank
+  }
+
+  pub fn vram_accessible(&self) -> bool {
+    self.mode != Mode::Reading_OAM_and_VRAM
   }
 
   // FIXME: Hmm, can't actually use these functions since they lead to borrowing
@@ -437,6 +541,43 @@ pub struct Tile {
 
 
 
+// Only the registers and timing: video memory lives in RAM, and pixels are
+// redrawn every frame.
+impl State for LCD {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8((&self.control).into())?;
+    out.write_u8(self.scroll_y)?;
+    out.write_u8(self.scroll_x)?;
+    out.write_u8(self.y_coordinate)?;
+    out.write_u8(self.bg_palette.into())?;
+    out.write_u8(self.stat_sources)?;
+    out.write_u8(self.mode as u8)?;
+    out.write_u16_le(self.dot)?;
+    out.write_u8(self.stat_line as u8)?;
+    out.write_u8(self.y_compare)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.control = input.read_u8()?.into();
+    self.scroll_y = input.read_u8()?;
+    self.scroll_x = input.read_u8()?;
+    self.y_coordinate = input.read_u8()?;
+    self.bg_palette = input.read_u8()?.into();
+    self.stat_sources = input.read_u8()? & 0x78;
+    self.mode = match input.read_u8()? {
+      0 => Mode::HBlank,
+      1 => Mode::VBlank,
+      2 => Mode::Reading_OAM,
+      3 => Mode::Reading_OAM_and_VRAM,
+      _ => return Err(state::invalid_data("LCD mode")),
+    };
+    self.dot = input.read_u16_le()?;
+    self.stat_line = input.read_u8()? > 0;
+    self.y_compare = input.read_u8()?;
+    Ok(())
+  }
+}
+
 // The background is a 256*256 pixels surface for drawing 32*32 tiles from the
 // tile map.  It can be scrolled, and is wrapped in both axes.
 struct Background {
@@ -1,68 +1,153 @@
 pub mod cpu;
 pub mod lcd;
 pub mod apu;
+pub mod boot_rom;
+pub mod bus;
+pub mod coverage;
 pub mod hardware;
+pub mod model;
+pub mod printer;
+pub mod serial;
+pub mod state;
+pub mod trace;
 
 mod utils;
 
-use self::cpu::Cpu;
+use self::boot_rom::BootRom;
+use self::bus::Bus;
+use self::cpu::{Cpu, Lockup, R16};
+use std::io::{Read, Write};
+
 use self::hardware::Hardware;
+use self::model::Model;
+use self::state::{State, StateError};
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 pub const GB_FREQ: u32 = 4194304;
 
-pub struct GB {
-  pub cpu: Cpu,
+pub struct GB<B = Hardware> {
+  pub cpu: Cpu<B>,
 }
 
 impl GB {
   pub fn new() -> GB {
+    GB::with_bus(Hardware::new())
+  }
+}
+
+impl<B: Bus> GB<B> {
+  pub fn with_bus(bus: B) -> Self {
     GB {
-      cpu: Cpu::new(Hardware::new()),
+      cpu: Cpu::new(bus),
     }
   }
 
   pub fn load_rom(&mut self, rom: &[u8], offset: u16) {
     // Copy ROM into RAM at offset, stopping at 0x7fff, or when ROM is empty.
     for (addr,idx) in (offset..0x8000).zip(0..rom.len()) {
-      self.cpu.write(addr, rom[idx]);
+      self.cpu.bus.write(addr, rom[idx]);
     }
   }
 
-  pub fn run_for(&mut self, cycles: u64) {
+  pub fn run_for(&mut self, cycles: u64) -> Result<(), Lockup> {
     let mut c : u64 = 0;
 
     while c < cycles {
-      c += self.cpu.step() as u64;
+      c += self.cpu.step()? as u64;
     }
+    Ok(())
   }
 
   pub fn reset(&mut self) {
     self.cpu.clear_registers();
   }
 
-  pub fn run(&mut self) {
+  // Run until the CPU locks up
+  pub fn run(&mut self) -> Lockup {
     loop {
-      self.cpu.step();
+      if let Err(lockup) = self.cpu.step() {
+        return lockup;
+      }
+    }
+  }
+}
+
+impl<B: Bus + AsMut<Hardware>> GB<B> {
+  // Skip the boot ROM: set up the registers it leaves for the cartridge, and
+  // start at $0100.  Load the ROM first, as the flags depend on its header.
+  pub fn reset_to(&mut self, model: Model) {
+    self.cpu.bus.as_mut().model = model;
+    self.cpu.clear_registers();
+    let [af, bc, de, hl] = model.registers(|a| self.cpu.bus.peek(a));
+    self.cpu.rr_set(R16::AF, af);
+    self.cpu.rr_set(R16::BC, bc);
+    self.cpu.rr_set(R16::DE, de);
+    self.cpu.rr_set(R16::HL, hl);
+    self.cpu.rr_set(R16::SP, 0xFFFE);
+    self.cpu.rr_set(R16::PC, 0x0100);
+    self.cpu.ime = 0;
+
+    for (addr, w) in model.io() {
+      self.cpu.bus.write(addr, w);
+    }
+  }
+}
+
+impl<B: Bus + State> GB<B> {
+  // Snapshot the whole machine
+  pub fn save_state<W: Write>(&self, out: &mut W) -> Result<(), StateError> {
+    out.write_all(state::MAGIC)?;
+    out.write_u16_le(state::VERSION)?;
+    self.cpu.save(out)?;
+    Ok(())
+  }
+
+  // Restore a snapshot written by save_state.  On error the machine is left
+  // in an unspecified state.
+  pub fn load_state<R: Read>(&mut self, input: &mut R) -> Result<(), StateError> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != state::MAGIC {
+      return Err(StateError::BadMagic);
+    }
+
+    let version = input.read_u16_le()?;
+    if version != state::VERSION {
+      return Err(StateError::UnsupportedVersion(version));
     }
+
+    self.cpu.load(input)?;
+    Ok(())
+  }
+}
+
+impl GB {
+  // Power on into boot_rom, which hands over to the cartridge at $0100.  Load
+  // the ROM first, as the boot ROM checks its header.
+  pub fn boot(&mut self, boot_rom: BootRom) {
+    self.cpu.bus.map_boot_rom(boot_rom);
+    self.cpu.clear_registers();
+    self.cpu.ime = 0;
   }
 
   pub fn tile_map(&self) -> &[u8] {
-    let select = (self.cpu.read(0xFF40) & 0x08) > 0;
+    let select = (self.cpu.bus.peek(0xFF40) & 0x08) > 0;
     match select {
-      false => &self.cpu.hardware.ram[0x9800..0x9C00],
-      true => &self.cpu.hardware.ram[0x9C00..0xA000],
+      false => &self.cpu.bus.ram[0x9800..0x9C00],
+      true => &self.cpu.bus.ram[0x9C00..0xA000],
     }
   }
 
   pub fn tile_data(&self) -> &[u8] {
-    let select = (self.cpu.read(0xFF40) & 0x10) > 0;
+    let select = (self.cpu.bus.peek(0xFF40) & 0x10) > 0;
     match select {
-      false => &self.cpu.hardware.ram[0x8800..0x9800],
-      true => &self.cpu.hardware.ram[0x8000..0x9000],
+      false => &self.cpu.bus.ram[0x8800..0x9800],
+      true => &self.cpu.bus.ram[0x8000..0x9000],
     }
   }
 
   pub fn tile_pattern_table(&self) -> &[u8] {
-    &self.cpu.hardware.ram[0x8000..0x9000]
+    &self.cpu.bus.ram[0x8000..0x9000]
   }
 }
@@ -0,0 +1,84 @@
+// Game Boy models, and the state their boot ROM leaves the machine in when it
+// jumps to the cartridge at $0100.  Values from Pan Docs, "Power Up Sequence".
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Model {
+  // Original Game Boy
+  Dmg,
+  // Game Boy Pocket
+  Mgb,
+  // Super Game Boy
+  Sgb,
+  // Game Boy Color
+  Cgb,
+}
+
+// Set by the boot ROM from the header checksum on DMG and MGB
+const HEADER_CHECKSUM : u16 = 0x014D;
+
+// I/O registers the boot ROMs leave in the same state.  The boot sound leaves
+// channel 1 on but silent, so NR52 reads $F1: trigger it at volume 0 before
+// setting NR12 to its final value, which only takes effect on the next
+// trigger.
+const IO : [(u16, u8); 37] = [
+  (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, 0x7E), (0xFF05, 0x00),
+  (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+  // NR52 first, as the APU is off until then
+  (0xFF26, 0xF1),
+  (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0x08), (0xFF13, 0xFF),
+  (0xFF14, 0xBF), (0xFF12, 0xF3), (0xFF16, 0x3F), (0xFF17, 0x00),
+  (0xFF18, 0xFF), (0xFF19, 0x3F), (0xFF1A, 0x7F), (0xFF1B, 0xFF),
+  (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0x3F), (0xFF20, 0xFF),
+  (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0x3F), (0xFF24, 0x77),
+  (0xFF25, 0xF3), (0xFF40, 0x91), (0xFF42, 0x00), (0xFF43, 0x00),
+  (0xFF44, 0x00), (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF4A, 0x00),
+  (0xFF4B, 0x00),
+];
+
+impl Model {
+  pub fn parse(s: &str) -> Option<Self> {
+    Some(match s {
+      "dmg" => Model::Dmg,
+      "mgb" => Model::Mgb,
+      "sgb" => Model::Sgb,
+      "cgb" => Model::Cgb,
+      _ => return None,
+    })
+  }
+
+  // For save states
+  pub fn from_u8(v: u8) -> Option<Self> {
+    match v {
+      0 => Some(Model::Dmg),
+      1 => Some(Model::Mgb),
+      2 => Some(Model::Sgb),
+      3 => Some(Model::Cgb),
+      _ => None,
+    }
+  }
+
+  // AF, BC, DE and HL.  peek reads the cartridge header.
+  pub fn registers<F: Fn(u16) -> u8>(self, peek: F) -> [u16; 4] {
+    // H and C are set unless the checksum is 0
+    let hc = if peek(HEADER_CHECKSUM) == 0 { 0x00 } else { 0x30 };
+    match self {
+      Model::Dmg => [0x0180 | hc, 0x0013, 0x00D8, 0x014D],
+      Model::Mgb => [0xFF80 | hc, 0x0013, 0x00D8, 0x014D],
+      Model::Sgb => [0x0100, 0x0014, 0x0000, 0xC060],
+      Model::Cgb => [0x1180, 0x0000, 0xFF56, 0x000D],
+    }
+  }
+
+  // I/O registers as the boot ROM leaves them, in the order to write them
+  pub fn io(self) -> Vec<(u16, u8)> {
+    let mut io = IO.to_vec();
+    let (div, stat) = match self {
+      Model::Dmg | Model::Mgb => (0xAB, 0x85),
+      Model::Sgb | Model::Cgb => (0x00, 0x81),
+    };
+    io.push((0xFF04, div));
+    io.push((0xFF41, stat));
+    io.push((0xFFFF, 0x00));
+    io
+  }
+}
@@ -0,0 +1,333 @@
+// The Game Boy Printer, plugged into the serial port.  Each print job is
+// written as a PNG file in a directory.
+//
+// The Game Boy drives the clock and sends packets:
+//
+//   $88 $33  command  compression  length (LE)  data  checksum (LE)  $00 $00
+//
+// The checksum is the sum of the bytes from the command to the end of the
+// data.  The printer answers $00 to every byte but the last two: $81 to say it
+// is there, then its status.
+//
+// Commands:
+//
+//   INIT    clear the image buffer
+//   DATA    add 2 rows of 20 tiles (640 bytes) to the buffer.  An empty DATA
+//           packet ends the image.
+//   PRINT   print the buffer.  Data is the number of copies, the margins
+//           (lines fed before in the high nibble, after in the low one), the
+//           palette and the exposure.
+//   STATUS  only ask for the status
+//
+// Compressed data is a run-length encoding: a byte n below $80 is followed
+// by n+1 bytes to copy, and a byte n from $80 by one byte to repeat n-$80+2
+// times.
+//
+// A job lasts until a print with a margin after it: games print long images
+// in several parts, with no margin in between.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use gb::serial::Peer;
+use png;
+
+const MAGIC : [u8; 2] = [0x88, 0x33];
+const ALIVE : u8 = 0x81;
+
+const INIT : u8 = 0x01;
+const PRINT : u8 = 0x02;
+const DATA : u8 = 0x04;
+const STATUS : u8 = 0x0F;
+
+// Status bits
+const CHECKSUM_ERROR : u8 = 0x01;
+const BUSY : u8 = 0x02;
+const IMAGE_FULL : u8 = 0x04;
+const UNPROCESSED : u8 = 0x08;
+
+// Status packets during which a print is going on
+const PRINT_POLLS : u8 = 2;
+
+// 20 tiles by 18 rows of tiles at most, 16 bytes per tile
+const WIDTH : usize = 160;
+const TILES_PER_ROW : usize = WIDTH / 8;
+const BUFFER_SIZE : usize = TILES_PER_ROW * 18 * 16;
+
+// Blank rows of pixels per margin line
+const MARGIN_ROWS : usize = 8;
+
+// Palette the printer uses when given 0
+const DEFAULT_PALETTE : u8 = 0xE4;
+
+// Gray levels of the 4 shades, from white to black
+const SHADES : [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+// Where the printer is in the packet
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stage {
+  Magic(usize),
+  Command,
+  Compression,
+  Length(usize),
+  Data,
+  Checksum(usize),
+  Alive,
+  Status,
+}
+
+pub struct Printer {
+  dir: PathBuf,
+
+  stage: Stage,
+  command: u8,
+  compressed: bool,
+  length: u16,
+  data: Vec<u8>,
+  // Sum computed, and the one received
+  sum: u16,
+  checksum: u16,
+
+  status: u8,
+  busy_polls: u8,
+
+  // Tile data waiting to be printed
+  buffer: Vec<u8>,
+  // Shades of the current job, WIDTH per row
+  job: Vec<u8>,
+}
+
+impl Printer {
+  // Print into dir, which is created if needed
+  pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+    fs::create_dir_all(&dir)?;
+    Ok(Printer {
+      dir: dir.as_ref().to_path_buf(),
+      stage: Stage::Magic(0),
+      command: 0,
+      compressed: false,
+      length: 0,
+      data: Vec::new(),
+      sum: 0,
+      checksum: 0,
+      status: 0,
+      busy_polls: 0,
+      buffer: Vec::new(),
+      job: Vec::new(),
+    })
+  }
+
+  // Take the next byte of a packet.  Return the answer.
+  fn receive(&mut self, w: u8) -> u8 {
+    let mut answer = 0x00;
+
+    self.stage = match self.stage {
+      Stage::Magic(i) if w == MAGIC[i] => {
+        if i + 1 < MAGIC.len() {
+          Stage::Magic(i + 1)
+        } else {
+          Stage::Command
+        }
+      }
+      // Out of sync: wait for the next packet
+      Stage::Magic(_) if w == MAGIC[0] => Stage::Magic(1),
+      Stage::Magic(_) => Stage::Magic(0),
+
+      Stage::Command => {
+        self.command = w;
+        self.sum = w as u16;
+        Stage::Compression
+      }
+      Stage::Compression => {
+        self.compressed = w & 0x01 > 0;
+        self.sum = self.sum.wrapping_add(w as u16);
+        Stage::Length(0)
+      }
+      Stage::Length(i) => {
+        self.sum = self.sum.wrapping_add(w as u16);
+        if i == 0 {
+          self.length = w as u16;
+          Stage::Length(1)
+        } else {
+          self.length |= (w as u16) << 8;
+          self.data.clear();
+          if self.length > 0 { Stage::Data } else { Stage::Checksum(0) }
+        }
+      }
+      Stage::Data => {
+        self.sum = self.sum.wrapping_add(w as u16);
+        self.data.push(w);
+        if self.data.len() < self.length as usize {
+          Stage::Data
+        } else {
+          Stage::Checksum(0)
+        }
+      }
+      Stage::Checksum(0) => {
+        self.checksum = w as u16;
+        Stage::Checksum(1)
+      }
+      Stage::Checksum(_) => {
+        self.checksum |= (w as u16) << 8;
+        self.execute();
+        Stage::Alive
+      }
+      Stage::Alive => {
+        answer = ALIVE;
+        Stage::Status
+      }
+      Stage::Status => {
+        answer = self.status;
+        Stage::Magic(0)
+      }
+    };
+
+    answer
+  }
+
+  fn execute(&mut self) {
+    if self.sum != self.checksum {
+      self.status |= CHECKSUM_ERROR;
+      return;
+    }
+    self.status &= !CHECKSUM_ERROR;
+
+    match self.command {
+      INIT => {
+        self.buffer.clear();
+        self.status = 0;
+        self.busy_polls = 0;
+      }
+
+      DATA => {
+        let data = if self.compressed {
+          decompress(&self.data)
+        } else {
+          self.data.clone()
+        };
+        let room = BUFFER_SIZE - self.buffer.len();
+        if data.len() > room {
+          self.status |= IMAGE_FULL;
+        }
+        self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+        if !self.buffer.is_empty() {
+          self.status |= UNPROCESSED;
+        }
+      }
+
+      PRINT if self.data.len() >= 4 => {
+        let (copies, margins, palette) = (self.data[0], self.data[1],
+                                          self.data[2]);
+        self.print(copies, margins >> 4, margins & 0x0F, palette);
+        self.buffer.clear();
+        self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | BUSY;
+        self.busy_polls = PRINT_POLLS;
+      }
+
+      STATUS if self.busy_polls > 0 => {
+        self.busy_polls -= 1;
+        if self.busy_polls == 0 {
+          self.status &= !BUSY;
+        }
+      }
+
+      _ => {}
+    }
+  }
+
+  fn print(&mut self, copies: u8, before: u8, after: u8, palette: u8) {
+    let palette = if palette == 0 { DEFAULT_PALETTE } else { palette };
+
+    self.feed(before);
+    let image = self.decode(palette);
+    for _ in 0..copies {
+      self.job.extend_from_slice(&image);
+    }
+    self.feed(after);
+
+    if after > 0 {
+      self.finish_job();
+    }
+  }
+
+  // Blank paper
+  fn feed(&mut self, lines: u8) {
+    let len = self.job.len() + lines as usize * MARGIN_ROWS * WIDTH;
+    self.job.resize(len, 0xFF);
+  }
+
+  // Buffer as rows of shades, in whole rows of tiles
+  fn decode(&self, palette: u8) -> Vec<u8> {
+    let tile_rows = self.buffer.len() / (TILES_PER_ROW * 16);
+    let mut image = vec![0xFF; tile_rows * 8 * WIDTH];
+
+    let tiles = self.buffer.chunks(16).take(tile_rows * TILES_PER_ROW);
+    for (t, tile) in tiles.enumerate() {
+      let (tx, ty) = (t % TILES_PER_ROW, t / TILES_PER_ROW);
+      for (line, bytes) in tile.chunks(2).enumerate() {
+        let y = ty * 8 + line;
+        for px in 0..8 {
+          let bit = 7 - px;
+          let color = ((bytes[1] >> bit) & 1) << 1 | ((bytes[0] >> bit) & 1);
+          let shade = (palette >> (color * 2)) & 0x03;
+          image[y * WIDTH + tx * 8 + px] = SHADES[shade as usize];
+        }
+      }
+    }
+
+    image
+  }
+
+  // Write the job to the next free print-NNN.png
+  fn finish_job(&mut self) {
+    if self.job.is_empty() {
+      return;
+    }
+
+    let path = (1..).map(|n| self.dir.join(format!("print-{:03}.png", n)))
+      .find(|p| !p.exists()).unwrap();
+    let height = (self.job.len() / WIDTH) as u32;
+    let result = File::create(&path).and_then(|f| {
+      png::write_gray(&mut BufWriter::new(f), WIDTH as u32, height, &self.job)
+    });
+    match result {
+      Ok(()) => println!("Printed to {}", path.display()),
+      Err(e) => println!("Error writing {}: {}", path.display(), e),
+    }
+
+    self.job.clear();
+  }
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut i = 0;
+  while i < data.len() {
+    let n = data[i] as usize;
+    i += 1;
+    if n < 0x80 {
+      let end = (i + n + 1).min(data.len());
+      out.extend_from_slice(&data[i..end]);
+      i = end;
+    } else if i < data.len() {
+      let len = out.len() + n - 0x80 + 2;
+      out.resize(len, data[i]);
+      i += 1;
+    }
+  }
+  out
+}
+
+impl Peer for Printer {
+  fn exchange(&mut self, out: u8) -> Option<u8> {
+    Some(self.receive(out))
+  }
+}
+
+// Print what is left when unplugged
+impl Drop for Printer {
+  fn drop(&mut self) {
+    self.finish_job();
+  }
+}
@@ -0,0 +1,486 @@
+// The serial port, and what is plugged into it.
+//
+// A transfer shifts the 8 bits of SB out while the 8 bits of the other side
+// shift in, on the clock of either side.  With the internal clock, a bit goes
+// every 512 T-cycles (8192 Hz); with the external clock, the transfer waits
+// for the peer to clock a byte in.  Both end with the serial interrupt.
+//
+// Peers exchange whole bytes: the one driving the clock sends its byte at the
+// start of the transfer, and the bits only start shifting once the other byte
+// is back.  A peer that does not answer in time is given up on, as if
+// unplugged.
+//
+// Two emulators are linked over a stream socket by messages of three bytes: a
+// tag, the number of the transfer, then the byte.  TRANSFER starts a transfer
+// clocked by the sender, and the other side answers with REPLY.  CANCEL tells
+// it the sender gave up on a transfer, which must not be answered anymore.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::collections::VecDeque;
+
+use gb::GB_FREQ;
+use gb::printer::Printer;
+use gb::state::State;
+use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
+
+// T-cycles per bit with the internal clock
+const BIT_CYCLES : u16 = 512;
+
+// T-cycles to wait for the peer to answer a transfer on our clock
+const ANSWER_TIMEOUT : u32 = GB_FREQ / 10;
+
+// SC bits
+const TRANSFER : u8 = 0x80;
+const INTERNAL_CLOCK : u8 = 0x01;
+
+pub const SERIAL_INTERRUPT : u8 = 0x08;
+
+pub trait Peer {
+  // Send out for a transfer on our clock.  Return the byte received if the
+  // peer answers right away.
+  fn exchange(&mut self, out: u8) -> Option<u8>;
+
+  // Byte received for the last exchange, once it has arrived
+  fn answer(&mut self) -> Option<u8> {
+    None
+  }
+
+  // Give up on the last exchange
+  fn cancel(&mut self) {}
+
+  // Byte sent by a peer driving the clock, if it started a transfer
+  fn poll(&mut self) -> Option<u8> {
+    None
+  }
+
+  // Answer the transfer returned by poll
+  fn reply(&mut self, _w: u8) {}
+}
+
+// Nothing plugged in: the line stays high
+pub struct NullPeer;
+
+impl Peer for NullPeer {
+  fn exchange(&mut self, _out: u8) -> Option<u8> {
+    Some(0xFF)
+  }
+}
+
+// Output wired to input
+pub struct Loopback;
+
+impl Peer for Loopback {
+  fn exchange(&mut self, out: u8) -> Option<u8> {
+    Some(out)
+  }
+}
+
+// Writes the bytes sent, and receives nothing
+pub struct LogPeer<W: Write> {
+  out: W,
+}
+
+impl<W: Write> LogPeer<W> {
+  pub fn new(out: W) -> Self {
+    LogPeer { out }
+  }
+}
+
+impl<W: Write> Peer for LogPeer<W> {
+  fn exchange(&mut self, out: u8) -> Option<u8> {
+    if let Err(e) = self.out.write_all(&[out]).and_then(|_| self.out.flush()) {
+      println!("Serial log error: {}", e);
+    }
+    Some(0xFF)
+  }
+}
+
+// Message tags on a link
+const LINK_TRANSFER : u8 = 0;
+const LINK_REPLY : u8 = 1;
+const LINK_CANCEL : u8 = 2;
+
+// A socket to another emulator
+pub trait Socket: Read + Write {
+  fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+  fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+    TcpStream::set_nonblocking(self, nonblocking)
+  }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+  fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+    UnixStream::set_nonblocking(self, nonblocking)
+  }
+}
+
+// Link cable to another emulator.  A broken link behaves as if unplugged.
+pub struct LinkPeer<S: Socket> {
+  socket: Option<S>,
+  // Partial message
+  buf: Vec<u8>,
+  // Number of our last transfer, and whether it waits for its reply
+  id: u8,
+  pending: bool,
+  // Transfers started by the other side and not answered yet, as numbers and
+  // bytes, and the number of the one to reply to
+  transfers: VecDeque<(u8, u8)>,
+  replying: u8,
+}
+
+impl<S: Socket> LinkPeer<S> {
+  pub fn new(socket: S) -> io::Result<Self> {
+    socket.set_nonblocking(true)?;
+    Ok(LinkPeer {
+      socket: Some(socket),
+      buf: Vec::new(),
+      id: 0,
+      pending: false,
+      transfers: VecDeque::new(),
+      replying: 0,
+    })
+  }
+
+  fn send(&mut self, tag: u8, id: u8, w: u8) {
+    let result = match self.socket {
+      Some(ref mut s) => s.write_all(&[tag, id, w]),
+      None => return,
+    };
+    if let Err(e) = result {
+      self.disconnect(e);
+    }
+  }
+
+  // Next message, if one has arrived
+  fn receive(&mut self) -> Option<(u8, u8, u8)> {
+    while self.buf.len() < 3 {
+      let mut byte = [0];
+      let result = match self.socket {
+        Some(ref mut s) => s.read(&mut byte),
+        None => return None,
+      };
+      match result {
+        Ok(0) => {
+          self.disconnect(io::ErrorKind::UnexpectedEof.into());
+          return None;
+        }
+        Ok(_) => self.buf.push(byte[0]),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+        Err(e) => {
+          self.disconnect(e);
+          return None;
+        }
+      }
+    }
+    let msg = (self.buf[0], self.buf[1], self.buf[2]);
+    self.buf.clear();
+    Some(msg)
+  }
+
+  // Handle the messages that have arrived.  Return the reply to our transfer
+  // if it is among them.
+  fn update(&mut self) -> Option<u8> {
+    let mut answer = None;
+    while let Some((tag, id, w)) = self.receive() {
+      match tag {
+        // Both sides drive the clock: nothing gets through
+        LINK_TRANSFER if self.pending => self.send(LINK_REPLY, id, 0xFF),
+        LINK_TRANSFER => self.transfers.push_back((id, w)),
+        LINK_REPLY if self.pending && id == self.id => {
+          self.pending = false;
+          answer = Some(w);
+        }
+        LINK_CANCEL => self.transfers.retain(|&(i, _)| i != id),
+        // A reply too late for its transfer
+        _ => {}
+      }
+    }
+
+    if self.pending && self.socket.is_none() {
+      self.pending = false;
+      answer = Some(0xFF);
+    }
+    answer
+  }
+
+  fn disconnect(&mut self, e: io::Error) {
+    println!("Serial link lost: {}", e);
+    self.socket = None;
+  }
+}
+
+impl<S: Socket> Peer for LinkPeer<S> {
+  fn exchange(&mut self, out: u8) -> Option<u8> {
+    self.id = self.id.wrapping_add(1);
+    self.pending = true;
+    let id = self.id;
+    self.send(LINK_TRANSFER, id, out);
+    self.update()
+  }
+
+  fn answer(&mut self) -> Option<u8> {
+    self.update()
+  }
+
+  fn cancel(&mut self) {
+    if self.pending {
+      self.pending = false;
+      let id = self.id;
+      self.send(LINK_CANCEL, id, 0);
+    }
+  }
+
+  fn poll(&mut self) -> Option<u8> {
+    self.update();
+    let (id, w) = self.transfers.pop_front()?;
+    self.replying = id;
+    Some(w)
+  }
+
+  fn reply(&mut self, w: u8) {
+    let id = self.replying;
+    self.send(LINK_REPLY, id, w);
+  }
+}
+
+// Open the peer described by spec:
+//
+//   null                 nothing plugged in
+//   loopback             output wired to input
+//   log:PATH             write the bytes sent to PATH
+//   printer:DIR          Game Boy Printer, printing PNG files into DIR
+//   tcp-listen:PORT      wait for another emulator on this machine to connect
+//   tcp-listen:HOST:PORT same, on the interface of HOST (0.0.0.0 for all)
+//   tcp:HOST:PORT        connect to another emulator
+//   unix-listen:PATH     same, over a Unix socket
+//   unix:PATH
+pub fn open(spec: &str) -> io::Result<Box<dyn Peer>> {
+  let (kind, arg) = match spec.find(':') {
+    Some(i) => (&spec[..i], &spec[i + 1..]),
+    None => (spec, ""),
+  };
+
+  Ok(match kind {
+    "null" => Box::new(NullPeer),
+    "loopback" => Box::new(Loopback),
+    "log" => Box::new(LogPeer::new(File::create(arg)?)),
+    "printer" => Box::new(Printer::new(arg)?),
+    "tcp-listen" => {
+      let listener = if arg.contains(':') {
+        TcpListener::bind(arg)?
+      } else {
+        TcpListener::bind(("127.0.0.1", parse_port(arg)?))?
+      };
+      println!("Waiting for a serial link on {}...", listener.local_addr()?);
+      Box::new(LinkPeer::new(listener.accept()?.0)?)
+    }
+    "tcp" => Box::new(LinkPeer::new(TcpStream::connect(arg)?)?),
+    #[cfg(unix)]
+    "unix-listen" => {
+      let listener = UnixListener::bind(arg)?;
+      println!("Waiting for a serial link on {}...", arg);
+      Box::new(LinkPeer::new(listener.accept()?.0)?)
+    }
+    #[cfg(unix)]
+    "unix" => Box::new(LinkPeer::new(UnixStream::connect(arg)?)?),
+    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   format!("unknown serial peer {}", spec))),
+  })
+}
+
+fn parse_port(s: &str) -> io::Result<u16> {
+  s.parse().map_err(|_| {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("bad port {}", s))
+  })
+}
+
+pub struct Serial {
+  // SB
+  data: u8,
+  // SC
+  control: u8,
+  // Bits left to shift, and T-cycles until the next one, with the internal
+  // clock
+  bits: u8,
+  countdown: u16,
+  // The byte shifting in
+  incoming: u8,
+  // Whether the transfer on our clock waits for the peer to answer, and for
+  // how many T-cycles
+  waiting: bool,
+  waited: u32,
+
+  pub peer: Box<dyn Peer>,
+
+  // Bytes sent since power on
+  output: Vec<u8>,
+}
+
+impl Default for Serial {
+  fn default() -> Self {
+    Serial::new()
+  }
+}
+
+impl Serial {
+  pub fn new() -> Self {
+    Serial {
+      data: 0,
+      control: 0,
+      bits: 0,
+      countdown: 0,
+      incoming: 0,
+      waiting: false,
+      waited: 0,
+      peer: Box::new(NullPeer),
+      output: Vec::new(),
+    }
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match addr {
+      0xFF01 => self.data,
+      0xFF02 => self.control | 0x7E,
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, w: u8) {
+    match addr {
+      0xFF01 => self.data = w,
+      0xFF02 => {
+        self.control = w & (TRANSFER | INTERNAL_CLOCK);
+        self.bits = 0;
+        self.give_up();
+        if self.control == TRANSFER | INTERNAL_CLOCK {
+          self.start();
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  fn start(&mut self) {
+    self.output.push(self.data);
+    if cfg!(feature = "debug") {
+      print!("{}", self.data as char);
+    }
+
+    match self.peer.exchange(self.data) {
+      Some(w) => self.incoming = w,
+      None => {
+        self.waiting = true;
+        self.waited = 0;
+      }
+    }
+    self.bits = 8;
+    self.countdown = BIT_CYCLES;
+  }
+
+  fn give_up(&mut self) {
+    if self.waiting {
+      self.peer.cancel();
+      self.waiting = false;
+    }
+  }
+
+  // Advance by one T-cycle.  Return the interrupts requested, as IF bits.
+  pub fn step(&mut self) -> u8 {
+    if self.control & TRANSFER == 0 {
+      return 0;
+    }
+
+    self.countdown = self.countdown.saturating_sub(1);
+    if self.countdown > 0 {
+      return 0;
+    }
+    self.countdown = BIT_CYCLES;
+
+    if self.control & INTERNAL_CLOCK > 0 {
+      // Check for the answer once per bit time.  The bits only shift once it
+      // is there.
+      if self.waiting {
+        match self.peer.answer() {
+          Some(w) => {
+            self.incoming = w;
+            self.waiting = false;
+          }
+          None if self.waited < ANSWER_TIMEOUT => {
+            self.waited += BIT_CYCLES as u32;
+            return 0;
+          }
+          None => {
+            self.give_up();
+            self.incoming = 0xFF;
+          }
+        }
+      }
+
+      self.data = self.data << 1 | self.incoming >> 7;
+      self.incoming <<= 1;
+      self.bits = self.bits.saturating_sub(1);
+      if self.bits > 0 {
+        return 0;
+      }
+    } else {
+      // Waiting on the external clock: check for the peer once per bit time
+      match self.peer.poll() {
+        Some(w) => {
+          self.output.push(self.data);
+          self.peer.reply(self.data);
+          self.data = w;
+        }
+        None => return 0,
+      }
+    }
+
+    self.control &= !TRANSFER;
+    SERIAL_INTERRUPT
+  }
+
+  pub fn output(&self) -> &[u8] {
+    &self.output
+  }
+}
+
+// The peer stays plugged in
+impl State for Serial {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    out.write_u8(self.data)?;
+    out.write_u8(self.control)?;
+    out.write_u8(self.bits)?;
+    out.write_u16_le(self.countdown)?;
+    out.write_u8(self.incoming)?;
+    out.write_u8(self.waiting as u8)?;
+    out.write_u32_le(self.waited)
+  }
+
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+    self.data = input.read_u8()?;
+    self.control = input.read_u8()? & (TRANSFER | INTERNAL_CLOCK);
+    self.bits = input.read_u8()?.min(8);
+    self.countdown = input.read_u16_le()?;
+    self.incoming = input.read_u8()?;
+    // The peer never saw the transfer waited for: start it again
+    self.give_up();
+    let waiting = input.read_u8()? > 0;
+    self.waited = input.read_u32_le()?;
+    if waiting {
+      self.waiting = match self.peer.exchange(self.data) {
+        Some(w) => { self.incoming = w; false }
+        None => true,
+      };
+    }
+    Ok(())
+  }
+}
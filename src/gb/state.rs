@@ -0,0 +1,57 @@
+// Save states: a snapshot of the whole machine, from which emulation resumes
+// exactly where it was.
+//
+// A state file is the magic, a format version, then each component in a fixed
+// order (CPU registers, IME, model, memory, LCD, APU, boot ROM, serial
+// port).  Everything is little-endian.  Bump VERSION whenever the layout
+// changes.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC : &[u8; 4] = b"GBSS";
+pub const VERSION : u16 = 7;
+
+#[derive(Debug)]
+pub enum StateError {
+  Io(io::Error),
+  BadMagic,
+  UnsupportedVersion(u16),
+}
+
+impl fmt::Display for StateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      StateError::Io(ref err) => write!(f, "{}", err),
+      StateError::BadMagic => write!(f, "not a save state"),
+      StateError::UnsupportedVersion(v) =>
+        write!(f, "unsupported save state version {} (expected {})",
+               v, VERSION),
+    }
+  }
+}
+
+impl From<io::Error> for StateError {
+  fn from(err: io::Error) -> StateError {
+    StateError::Io(err)
+  }
+}
+
+// Implemented by every component holding emulation state.  load must read
+// exactly what save wrote.
+pub trait State {
+  fn save<W: Write>(&self, out: &mut W) -> io::Result<()>;
+  fn load<R: Read>(&mut self, input: &mut R) -> io::Result<()>;
+}
+
+// For enum values that are out of range in a state file
+pub fn invalid_data(what: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData,
+                 format!("invalid {} in save state", what))
+}
+
+// File holding the numbered save slot of a ROM: game.gb -> game.ss1
+pub fn slot_path<P: AsRef<Path>>(rom: P, slot: u8) -> PathBuf {
+  rom.as_ref().with_extension(format!("ss{}", slot))
+}
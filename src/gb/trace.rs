@@ -0,0 +1,191 @@
+// Instruction traces: one line per instruction, written before it runs.
+//
+// The Doctor format is the one of Gameboy Doctor and of the reference logs
+// published with it, so that a run can be diffed against another emulator:
+//
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// Those logs were taken with LY always reading $90: set doctor_ly on the
+// hardware to match them.
+//
+// The Disassembly format is meant to be read: registers, the instruction and
+// where it is, named by the CPU symbols.
+//
+// Tracing can start and stop when PC reaches an address, or after a number of
+// cycles run by the CPU since the tracer was attached.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use disassembler;
+use gb::bus::Bus;
+use gb::cpu::{Cpu, R8, R16};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+  Doctor,
+  Disassembly,
+}
+
+impl Format {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s {
+      "doctor" => Some(Format::Doctor),
+      "disasm" => Some(Format::Disassembly),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Trigger {
+  Pc(u16),
+  Cycle(u64),
+}
+
+impl Trigger {
+  // "pc=ADDR" or "cycle=N".  Numbers are decimal, or hexadecimal with a 0x or
+  // $ prefix.
+  pub fn parse(s: &str) -> Option<Self> {
+    let (kind, n) = s.split_at(s.find('=')?);
+    let n = &n[1..];
+    let n = match n.strip_prefix("0x").or_else(|| n.strip_prefix('$')) {
+      Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+      None => n.parse().ok()?,
+    };
+    match kind {
+      "pc" if n < 0x10000 => Some(Trigger::Pc(n as u16)),
+      "cycle" => Some(Trigger::Cycle(n)),
+      _ => None,
+    }
+  }
+
+  fn holds(self, pc: u16, cycles: u64) -> bool {
+    match self {
+      Trigger::Pc(addr) => pc == addr,
+      Trigger::Cycle(n) => cycles >= n,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Status {
+  Waiting,
+  Tracing,
+  Done,
+}
+
+pub struct Tracer {
+  out: Box<dyn Write>,
+  format: Format,
+  start: Option<Trigger>,
+  stop: Option<Trigger>,
+  status: Status,
+  // Cycles run by the CPU since the tracer was attached
+  cycles: u64,
+}
+
+impl Tracer {
+  // Trace every instruction to out
+  pub fn new(out: Box<dyn Write>, format: Format) -> Self {
+    Tracer {
+      out,
+      format,
+      start: None,
+      stop: None,
+      status: Status::Waiting,
+      cycles: 0,
+    }
+  }
+
+  pub fn create<P: AsRef<Path>>(path: P, format: Format) -> io::Result<Self> {
+    let file = BufWriter::new(File::create(path)?);
+    Ok(Tracer::new(Box::new(file), format))
+  }
+
+  // Start tracing at the instruction where the trigger holds
+  pub fn start_at(mut self, trigger: Trigger) -> Self {
+    self.start = Some(trigger);
+    self
+  }
+
+  // Stop tracing before the instruction where the trigger holds
+  pub fn stop_at(mut self, trigger: Trigger) -> Self {
+    self.stop = Some(trigger);
+    self
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.status == Status::Done
+  }
+
+  // Called before the CPU runs the instruction at PC
+  pub fn trace<B: Bus>(&mut self, cpu: &Cpu<B>) {
+    let pc = cpu.rr(R16::PC);
+
+    if self.status == Status::Waiting
+      && self.start.is_none_or(|t| t.holds(pc, self.cycles)) {
+      self.status = Status::Tracing;
+    }
+    if self.status == Status::Tracing
+      && self.stop.is_some_and(|t| t.holds(pc, self.cycles)) {
+      self.finish();
+    }
+    if self.status != Status::Tracing {
+      return;
+    }
+
+    let line = match self.format {
+      Format::Doctor => doctor_line(cpu),
+      Format::Disassembly => disassembly_line(cpu),
+    };
+    if let Err(e) = writeln!(self.out, "{}", line) {
+      println!("Error writing trace: {}", e);
+      self.status = Status::Done;
+    }
+  }
+
+  // Called after the instruction ran
+  pub fn tick(&mut self, cycles: u8) {
+    self.cycles += cycles as u64;
+  }
+
+  fn finish(&mut self) {
+    self.status = Status::Done;
+    if let Err(e) = self.out.flush() {
+      println!("Error writing trace: {}", e);
+    }
+  }
+}
+
+impl Drop for Tracer {
+  fn drop(&mut self) {
+    let _ = self.out.flush();
+  }
+}
+
+fn doctor_line<B: Bus>(cpu: &Cpu<B>) -> String {
+  let pc = cpu.rr(R16::PC);
+  let mem = |i: u16| cpu.bus.peek(pc.wrapping_add(i));
+  format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} \
+           L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+          cpu.r(R8::A), cpu.r(R8::F), cpu.r(R8::B), cpu.r(R8::C),
+          cpu.r(R8::D), cpu.r(R8::E), cpu.r(R8::H), cpu.r(R8::L),
+          cpu.rr(R16::SP), pc, mem(0), mem(1), mem(2), mem(3))
+}
+
+fn disassembly_line<B: Bus>(cpu: &Cpu<B>) -> String {
+  let pc = cpu.rr(R16::PC);
+  let inst = disassembler::decode_memory(|a| cpu.bus.peek(a), pc);
+  let text = inst.format_with(|a| cpu.symbols.name(a));
+  let location = match cpu.symbols.name(pc) {
+    Some(name) => format!("  ; {}", name),
+    None => String::new(),
+  };
+  format!("{:04x} {:02x} {:02x} AF:{:04x} BC:{:04x} DE:{:04x} HL:{:04x} \
+           SP:{:04x} {}{}",
+          pc, cpu.bus.peek(pc), cpu.bus.peek(pc.wrapping_add(1)),
+          cpu.rr(R16::AF), cpu.rr(R16::BC), cpu.rr(R16::DE), cpu.rr(R16::HL),
+          cpu.rr(R16::SP), text, location)
+}
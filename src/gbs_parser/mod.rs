@@ -1,11 +1,13 @@
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::fs::File;
 use std::path::Path;
 use std::result;
 
 pub mod read_binary;
+pub mod write_binary;
 
 use gbs_parser::read_binary::ReadBinary;
+use gbs_parser::write_binary::WriteBinary;
 
 macro_rules! fail {
   ($err:path) => (return Err($err));
@@ -27,7 +29,7 @@ impl From<io::Error> for GbsError {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Gbs {
   pub version:    u8,
   pub n_songs:    u8,
@@ -46,7 +48,11 @@ pub struct Gbs {
 
 pub fn load<P: AsRef<Path>>(path: P) -> self::Result<Gbs> {
   let f = try!(File::open(path));
-  let mut file = BufReader::new(f);
+  read(f)
+}
+
+pub fn read<R: Read>(reader: R) -> self::Result<Gbs> {
+  let mut file = BufReader::new(reader);
 
   let header = try!(file.read_str(3));
   if header != "GBS" {
@@ -100,3 +106,44 @@ pub fn load<P: AsRef<Path>>(path: P) -> self::Result<Gbs> {
     rom:        rom,
   })
 }
+
+pub fn save<P: AsRef<Path>>(gbs: &Gbs, path: P) -> self::Result<()> {
+  let mut file = BufWriter::new(File::create(path)?);
+  gbs.write(&mut file)?;
+  file.flush()?;
+  Ok(())
+}
+
+impl Gbs {
+  // Check the header fields that load would reject
+  pub fn validate(&self) -> self::Result<()> {
+    for &addr in &[self.load_addr, self.init_addr, self.play_addr] {
+      if !(0x400..=0x7fff).contains(&addr) {
+        fail!(GbsError::InvalidAddress)
+      }
+    }
+    Ok(())
+  }
+
+  // Serialize as a version 1 GBS file.  Fail on values that load would
+  // reject.
+  pub fn write<W: Write>(&self, out: &mut W) -> self::Result<()> {
+    self.validate()?;
+
+    out.write_all(b"GBS")?;
+    out.write_u8(1)?;
+    out.write_u8(self.n_songs)?;
+    out.write_u8(self.first_song)?;
+    out.write_u16_le(self.load_addr)?;
+    out.write_u16_le(self.init_addr)?;
+    out.write_u16_le(self.play_addr)?;
+    out.write_u16_le(self.sp)?;
+    out.write_u8(self.timer_mod)?;
+    out.write_u8(self.timer_ctrl)?;
+    out.write_str(&self.title, 32)?;
+    out.write_str(&self.author, 32)?;
+    out.write_str(&self.copyright, 32)?;
+    out.write_all(&self.rom)?;
+    Ok(())
+  }
+}
@@ -1,4 +1,4 @@
-use std::io::{self, BufReader, Read};
+use std::io::{self, Read};
 
 // Provide the following methods on types implementing Read.
 pub trait ReadBinary : Read {
@@ -21,6 +21,13 @@ pub trait ReadBinary : Read {
     Ok(((buf[0] as u16) << 8) | (buf[1] as u16))
   }
 
+  fn read_u32_le(&mut self) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    self.read_exact(&mut buf)?;
+    Ok(((buf[3] as u32) << 24) | ((buf[2] as u32) << 16)
+       | ((buf[1] as u32) << 8) | (buf[0] as u32))
+  }
+
   fn read_str(&mut self, len: usize) -> io::Result<String> {
     let mut ret = String::new();
     for b in self.bytes().take(len) {
@@ -33,5 +40,4 @@ pub trait ReadBinary : Read {
   }
 }
 
-// We only need it on BufReader for now.
-impl<R: Read> ReadBinary for BufReader<R> {}
+impl<R: Read> ReadBinary for R {}
@@ -0,0 +1,39 @@
+use std::io::{self, Write};
+
+// Provide the following methods on types implementing Write.  Counterpart of
+// ReadBinary.
+pub trait WriteBinary : Write {
+
+  fn write_u8(&mut self, w: u8) -> io::Result<()> {
+    self.write_all(&[w])
+  }
+
+  fn write_u16_le(&mut self, w: u16) -> io::Result<()> {
+    self.write_all(&[w as u8, (w >> 8) as u8])
+  }
+
+  fn write_u16_be(&mut self, w: u16) -> io::Result<()> {
+    self.write_all(&[(w >> 8) as u8, w as u8])
+  }
+
+  fn write_u32_le(&mut self, w: u32) -> io::Result<()> {
+    self.write_all(&[w as u8, (w >> 8) as u8, (w >> 16) as u8, (w >> 24) as u8])
+  }
+
+  fn write_u32_be(&mut self, w: u32) -> io::Result<()> {
+    self.write_all(&[(w >> 24) as u8, (w >> 16) as u8, (w >> 8) as u8, w as u8])
+  }
+
+  // Write exactly len bytes: the string is truncated or null-filled on the
+  // right to fit.  Like read_str, one char is one byte; chars that do not fit
+  // in a byte are replaced by '?'.
+  fn write_str(&mut self, s: &str, len: usize) -> io::Result<()> {
+    let mut buf = vec![0u8; len];
+    for (b, c) in buf.iter_mut().zip(s.chars()) {
+      *b = if (c as u32) < 0x100 { c as u8 } else { b'?' };
+    }
+    self.write_all(&buf)
+  }
+}
+
+impl<W: Write> WriteBinary for W {}
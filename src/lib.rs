@@ -1,6 +1,17 @@
+pub mod debugger;
+pub mod disassembler;
 pub mod gb;
 pub mod gb_parser;
 pub mod gbs_parser;
+pub mod player;
+pub mod playlist_parser;
+pub mod png;
+pub mod profiler;
+pub mod ripper;
+pub mod sink;
+pub mod symbols;
+pub mod test_runner;
+pub mod util;
 
 #[macro_use]
 extern crate glium;
@@ -0,0 +1,391 @@
+// Play back GBS files: run INIT for the selected track, then call PLAY at the
+// rate given by the header, and downsample the APU output.
+
+use std::fmt;
+
+use gb::GB;
+use gb::apu::sampler::Sampler;
+use gb::bus::Bus;
+use gb::coverage::Coverage;
+use gb::hardware::Hardware;
+use gb::model::Model;
+use gb::cpu::{Lockup, R8, R16};
+use gb::trace::Tracer;
+use gbs_parser::Gbs;
+use profiler::Profiler;
+use symbols::Symbols;
+
+// INIT and PLAY are called with this return address on the stack.  Reaching
+// it means the routine has returned.
+pub const IDLE_ADDR : u16 = 0xF00D;
+
+// Cycles between two v-blank interrupts
+pub const VBLANK_PERIOD : u32 = 70224;
+
+// Give up on INIT or PLAY routines that have not returned after that many
+// cycles (about a second)
+const CALL_BUDGET : u32 = 60 * VBLANK_PERIOD;
+
+#[derive(Debug)]
+pub enum PlayerError {
+  NoSuchTrack(u8),
+  // The routine did not return in time.  Holds the PC where we gave up.
+  InitTimeout(u16),
+  PlayTimeout(u16),
+  // The driver ran into an illegal opcode
+  Crashed(Lockup),
+}
+
+impl From<Lockup> for PlayerError {
+  fn from(lockup: Lockup) -> PlayerError {
+    PlayerError::Crashed(lockup)
+  }
+}
+
+impl fmt::Display for PlayerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      PlayerError::NoSuchTrack(t) => write!(f, "no track {}", t),
+      PlayerError::InitTimeout(pc) =>
+        write!(f, "INIT did not return (PC: {:04x})", pc),
+      PlayerError::PlayTimeout(pc) =>
+        write!(f, "PLAY did not return (PC: {:04x})", pc),
+      PlayerError::Crashed(lockup) =>
+        write!(f, "driver crashed at ${:04x} (illegal opcode ${:02x})",
+               lockup.addr, lockup.opcode),
+    }
+  }
+}
+
+pub struct GbsPlayer {
+  gbs: Gbs,
+  gb: GB<GbsBus>,
+  track: u8,
+  // Whether INIT has run for the track
+  started: bool,
+  sample_rate: u32,
+
+  // Cycles between two PLAY calls, and until the next one
+  play_period: u32,
+  play_countdown: u32,
+  // Cycles spent in the current PLAY call
+  play_cycles: u32,
+
+  // Number of stereo frames output since the start of the track
+  position: u64,
+  // For traces
+  symbols: Symbols,
+  profiler: Option<Profiler>,
+}
+
+impl GbsPlayer {
+  // Create a player on the first song given by the header.  INIT only runs
+  // once the track is played, unless another one is started before.
+  pub fn new(gbs: Gbs, sample_rate: u32) -> Self {
+    GbsPlayer {
+      play_period: play_period(&gbs),
+      gb: GB::with_bus(GbsBus::new(gbs.load_addr)),
+      track: gbs.first_song.saturating_sub(1),
+      gbs,
+      started: false,
+      sample_rate,
+      play_countdown: 0,
+      play_cycles: 0,
+      position: 0,
+      symbols: Symbols::new(),
+      profiler: None,
+    }
+  }
+
+  pub fn gbs(&self) -> &Gbs {
+    &self.gbs
+  }
+
+  pub fn track_count(&self) -> u8 {
+    self.gbs.n_songs
+  }
+
+  // 0-based
+  pub fn current_track(&self) -> u8 {
+    self.track
+  }
+
+  // Name the driver addresses in traces
+  pub fn set_symbols(&mut self, symbols: Symbols) {
+    self.gb.cpu.symbols = symbols.clone();
+    self.symbols = symbols;
+  }
+
+  // Trace the driver instructions.  The tracer is kept across tracks.
+  pub fn set_tracer(&mut self, tracer: Tracer) {
+    self.gb.cpu.tracer = Some(tracer);
+  }
+
+  // Record the memory accessed by the driver.  Coverage adds up across
+  // tracks.
+  pub fn set_coverage(&mut self, coverage: Coverage) {
+    self.gb.cpu.bus.hardware.coverage = Some(coverage);
+  }
+
+  pub fn coverage(&self) -> Option<&Coverage> {
+    self.gb.cpu.bus.hardware.coverage.as_ref()
+  }
+
+  // Profile the PLAY calls from now on
+  pub fn set_profiler(&mut self, profiler: Profiler) {
+    self.profiler = Some(profiler);
+  }
+
+  pub fn profiler(&self) -> Option<&Profiler> {
+    self.profiler.as_ref()
+  }
+
+  // Cycles between two PLAY calls
+  pub fn play_period(&self) -> u32 {
+    self.play_period
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  // Milliseconds played since the start of the track
+  pub fn position(&self) -> u32 {
+    (self.position * 1000 / self.sample_rate as u64) as u32
+  }
+
+  // Reset the machine and run INIT for the given 0-based track
+  pub fn start_track(&mut self, track: u8) -> Result<(), PlayerError> {
+    if track >= self.gbs.n_songs {
+      return Err(PlayerError::NoSuchTrack(track));
+    }
+
+    self.track = track;
+    self.started = false;
+    self.play_countdown = 0;
+    self.play_cycles = 0;
+    self.position = 0;
+
+    let tracer = self.gb.cpu.tracer.take();
+    let coverage = self.gb.cpu.bus.hardware.coverage.take();
+    self.gb = load_track(&self.gbs, track);
+    self.gb.cpu.symbols = self.symbols.clone();
+    self.gb.cpu.tracer = tracer;
+    self.gb.cpu.bus.hardware.coverage = coverage;
+    self.gb.cpu.bus.hardware.sampler = Some(Sampler::new(self.sample_rate));
+
+    // Run the INIT subroutine
+    let cpu = &mut self.gb.cpu;
+    let mut budget = CALL_BUDGET;
+    while cpu.rr(R16::PC) != IDLE_ADDR {
+      let cycles = cpu.step()? as u32;
+      budget = budget.checked_sub(cycles)
+        .ok_or(PlayerError::InitTimeout(cpu.rr(R16::PC)))?;
+    }
+
+    // The track starts when INIT returns
+    cpu.bus.hardware.sampler = Some(Sampler::new(self.sample_rate));
+    self.started = true;
+    Ok(())
+  }
+
+  // Run INIT for the current track if it has not run yet
+  fn start(&mut self) -> Result<(), PlayerError> {
+    if self.started {
+      return Ok(());
+    }
+    let track = self.track;
+    self.start_track(track)
+  }
+
+  // Go to the given position in the current track, in milliseconds.  Seeking
+  // forward emulates the driver without rendering audio, so it is much faster
+  // than pulling the samples.
+  pub fn seek(&mut self, ms: u32) -> Result<(), PlayerError> {
+    let target = ms as u64 * self.sample_rate as u64 / 1000;
+
+    self.start()?;
+    if target < self.position {
+      let track = self.track;
+      self.start_track(track)?;
+    }
+
+    while self.position < target && self.sampler().pop().is_some() {
+      self.position += 1;
+    }
+
+    self.sampler().skip = true;
+    let mut result = Ok(());
+    while self.position < target && result.is_ok() {
+      result = self.run();
+      self.position += self.sampler().take_skipped();
+    }
+    self.sampler().skip = false;
+
+    result
+  }
+
+  // Return the next stereo frame, with samples in [-1.0,1.0]
+  pub fn next_sample(&mut self) -> Result<(f32, f32), PlayerError> {
+    self.start()?;
+    loop {
+      if let Some(s) = self.sampler().pop() {
+        self.position += 1;
+        return Ok(s);
+      }
+      self.run()?;
+    }
+  }
+
+  // Fill buf with interleaved stereo frames
+  pub fn fill(&mut self, buf: &mut [f32]) -> Result<(), PlayerError> {
+    for frame in buf.chunks_mut(2) {
+      let (left, right) = self.next_sample()?;
+      frame[0] = left;
+      if frame.len() > 1 {
+        frame[1] = right;
+      }
+    }
+    Ok(())
+  }
+
+  // Run the CPU for one instruction if PLAY is running, or the rest of the
+  // hardware alone for one M-cycle otherwise.  Calls PLAY when it is time.
+  fn run(&mut self) -> Result<(), PlayerError> {
+    if self.gb.cpu.rr(R16::PC) != IDLE_ADDR {
+      let cycles = match self.profiler {
+        Some(ref mut p) => p.step(&mut self.gb.cpu),
+        None => self.gb.cpu.step(),
+      }? as u32;
+      self.play_countdown = self.play_countdown.saturating_sub(cycles);
+
+      self.play_cycles += cycles;
+      if self.play_cycles > CALL_BUDGET {
+        return Err(PlayerError::PlayTimeout(self.gb.cpu.rr(R16::PC)));
+      }
+    } else if self.play_countdown == 0 {
+      self.play_countdown = self.play_period;
+      self.play_cycles = 0;
+      self.gb.cpu.call(self.gbs.play_addr);
+      if let Some(ref mut p) = self.profiler {
+        p.enter(&self.gb.cpu);
+      }
+    } else {
+      self.gb.cpu.bus.hardware.tick();
+      self.play_countdown = self.play_countdown.saturating_sub(4);
+    }
+
+    Ok(())
+  }
+
+  fn sampler(&mut self) -> &mut Sampler {
+    self.gb.cpu.bus.hardware.sampler.as_mut().expect("No sampler")
+  }
+}
+
+impl Iterator for GbsPlayer {
+  type Item = (f32, f32);
+
+  // Stop if the driver hangs.  Use next_sample to know why.
+  fn next(&mut self) -> Option<(f32, f32)> {
+    self.next_sample().ok()
+  }
+}
+
+// The hardware as GBS drivers see it.  RST instructions jump relative to the
+// load address, as the GBS data does not cover the vectors at $0000.  No
+// interrupt is ever pending: the player calls PLAY itself.
+pub struct GbsBus {
+  pub hardware: Hardware,
+  load_addr: u16,
+}
+
+impl GbsBus {
+  pub fn new(load_addr: u16) -> Self {
+    GbsBus {
+      hardware: Hardware::new(),
+      load_addr,
+    }
+  }
+}
+
+impl Bus for GbsBus {
+  fn read(&mut self, addr: u16) -> u8 {
+    self.hardware.read(addr)
+  }
+
+  fn write(&mut self, addr: u16, w: u8) {
+    self.hardware.write(addr, w);
+  }
+
+  fn fetch(&mut self, addr: u16) -> u8 {
+    self.hardware.fetch(addr)
+  }
+
+  fn peek(&self, addr: u16) -> u8 {
+    self.hardware.peek(addr)
+  }
+
+  fn tick(&mut self) {
+    self.hardware.tick();
+  }
+
+  fn rst_vector(&self, n: u8) -> u16 {
+    self.load_addr + n as u16
+  }
+}
+
+impl AsRef<Hardware> for GbsBus {
+  fn as_ref(&self) -> &Hardware {
+    &self.hardware
+  }
+}
+
+impl AsMut<Hardware> for GbsBus {
+  fn as_mut(&mut self) -> &mut Hardware {
+    &mut self.hardware
+  }
+}
+
+// A fresh machine with the GBS loaded, about to run INIT for the given track
+pub fn load_track(gbs: &Gbs, track: u8) -> GB<GbsBus> {
+  let mut gb = GB::with_bus(GbsBus::new(gbs.load_addr));
+  gb.load_rom(&gbs.rom, gbs.load_addr);
+
+  gb.reset_to(Model::Dmg);
+  // No screen: keep the PPU off, so drivers can use VRAM as RAM at any time
+  gb.cpu.bus.write(0xFF40, 0);
+
+  let cpu = &mut gb.cpu;
+  cpu.clear_ram();
+
+  cpu.rr_set(R16::SP, gbs.sp);
+  cpu.r_set(R8::A, track);
+  cpu.rr_set(R16::PC, IDLE_ADDR);
+  cpu.call(gbs.init_addr);
+  gb
+}
+
+// Cycles between two PLAY calls, from the TIMING section of the spec
+fn play_period(gbs: &Gbs) -> u32 {
+  // Bit 2 selects the timer over v-blank
+  if gbs.timer_ctrl & 0x04 == 0 {
+    return VBLANK_PERIOD;
+  }
+
+  // Cycles per timer tick
+  let divider = match gbs.timer_ctrl & 0x03 {
+    0 => 1024,
+    1 => 16,
+    2 => 64,
+    _ => 256,
+  };
+  let period = (256 - gbs.timer_mod as u32) * divider;
+
+  // Bit 7 asks for the GBC 2x CPU rate, which doubles the timer rate too
+  if gbs.timer_ctrl & 0x80 > 0 {
+    period / 2
+  } else {
+    period
+  }
+}
@@ -0,0 +1,190 @@
+// Extended M3U playlists, in the NEZPlug convention:
+//
+//   file.gbs::GBS,track,title,time,loop,fade
+//
+// Lines starting with '#' are comments.  Commas inside fields are escaped with
+// a backslash.  A decimal track number is 1-based, a track number prefixed by
+// '$' is hexadecimal and 0-based.  Times are [[h:]m:]s[.ms].  Every field after
+// the track is optional.  The loop point is ignored: the driver loops the song
+// by itself.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::result;
+
+pub type Result<T> = result::Result<T, PlaylistError>;
+
+#[derive(Debug)]
+pub enum PlaylistError {
+  Io(io::Error),
+  // Line number (1-based) of a malformed entry
+  InvalidEntry(usize),
+}
+
+impl From<io::Error> for PlaylistError {
+  fn from(err: io::Error) -> PlaylistError {
+    PlaylistError::Io(err)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+  pub file:       String,
+  // 0-based, as passed to the INIT routine
+  pub track:      u8,
+  pub title:      String,
+  // All times are in milliseconds
+  pub length:     Option<u32>,
+  pub fade:       Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Playlist {
+  pub entries: Vec<Entry>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> self::Result<Playlist> {
+  let file = BufReader::new(File::open(path)?);
+  let mut entries = Vec::new();
+
+  for (n, line) in file.lines().enumerate() {
+    let line = line?;
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    match parse_entry(line) {
+      Some(Some(entry)) => entries.push(entry),
+      // Entry for another system, skip it
+      Some(None) => {},
+      None => return Err(PlaylistError::InvalidEntry(n + 1)),
+    }
+  }
+
+  Ok(Playlist { entries })
+}
+
+// Return None if the line is malformed, and Some(None) if it is well-formed
+// but does not refer to a GBS file.
+fn parse_entry(line: &str) -> Option<Option<Entry>> {
+  let sep = line.find("::")?;
+  let file = &line[..sep];
+  let fields = split_fields(&line[sep + 2..]);
+
+  if !fields[0].eq_ignore_ascii_case("GBS") {
+    return Some(None);
+  }
+
+  let track = parse_track(fields.get(1)?)?;
+  let field = |i: usize| fields.get(i).map(|f| f.trim()).unwrap_or("");
+
+  Some(Some(Entry {
+    file: file.to_string(),
+    track,
+    title: field(2).to_string(),
+    length: parse_time(field(3)),
+    fade: parse_time(field(5)),
+  }))
+}
+
+// Split on commas that are not escaped by a backslash
+fn split_fields(s: &str) -> Vec<String> {
+  let mut fields = vec![String::new()];
+  let mut chars = s.chars();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        if let Some(next) = chars.next() {
+          fields.last_mut().unwrap().push(next);
+        }
+      }
+      ',' => fields.push(String::new()),
+      _ => fields.last_mut().unwrap().push(c),
+    }
+  }
+
+  fields
+}
+
+fn parse_track(s: &str) -> Option<u8> {
+  let s = s.trim();
+  if let Some(hex) = s.strip_prefix('$') {
+    u8::from_str_radix(hex, 16).ok()
+  } else {
+    s.parse::<u8>().ok().and_then(|t| t.checked_sub(1))
+  }
+}
+
+// Parse a duration as [[h:]m:]s[.ms] into milliseconds.  Return None for an
+// empty or malformed duration.
+pub fn parse_time(s: &str) -> Option<u32> {
+  let s = s.trim();
+  if s.is_empty() {
+    return None;
+  }
+
+  let (hms, ms) = match s.find('.') {
+    Some(dot) => {
+      // Treat the fraction as decimal: ".5" is 500ms
+      let frac = &s[dot + 1..];
+      if frac.is_empty() || frac.len() > 3 {
+        return None;
+      }
+      let ms = frac.parse::<u32>().ok()? * 10u32.pow(3 - frac.len() as u32);
+      (&s[..dot], ms)
+    }
+    None => (s, 0),
+  };
+
+  let mut secs = 0u32;
+  for part in hms.split(':') {
+    secs = secs.checked_mul(60)?.checked_add(part.parse::<u32>().ok()?)?;
+  }
+
+  secs.checked_mul(1000)?.checked_add(ms)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_entry, parse_time, parse_track};
+
+  #[test]
+  fn times() {
+    assert_eq!(parse_time("5"), Some(5000));
+    assert_eq!(parse_time("2:05"), Some(125000));
+    assert_eq!(parse_time("1:02:05"), Some(3725000));
+    assert_eq!(parse_time("0:01.5"), Some(1500));
+    assert_eq!(parse_time("0:01.025"), Some(1025));
+    assert_eq!(parse_time(" 3 "), Some(3000));
+    assert_eq!(parse_time(""), None);
+    assert_eq!(parse_time("1:"), None);
+    assert_eq!(parse_time("1.2345"), None);
+    assert_eq!(parse_time("x"), None);
+  }
+
+  #[test]
+  fn tracks() {
+    assert_eq!(parse_track("1"), Some(0));
+    assert_eq!(parse_track("12"), Some(11));
+    assert_eq!(parse_track("0"), None);
+    assert_eq!(parse_track("$0"), Some(0));
+    assert_eq!(parse_track("$1A"), Some(0x1A));
+    assert_eq!(parse_track("$100"), None);
+    assert_eq!(parse_track("$"), None);
+  }
+
+  #[test]
+  fn escaped_commas() {
+    let entry = parse_entry(r"song.gbs::GBS,$2,Hello\, world,1:30,,5")
+      .unwrap().unwrap();
+    assert_eq!(entry.file, "song.gbs");
+    assert_eq!(entry.track, 2);
+    assert_eq!(entry.title, "Hello, world");
+    assert_eq!(entry.length, Some(90000));
+    assert_eq!(entry.fade, Some(5000));
+  }
+}
@@ -0,0 +1,92 @@
+// Minimal PNG writer for 8-bit grayscale images.  The image data is not
+// compressed: zlib stored blocks are plenty for printer output.
+
+use std::io::{self, Write};
+
+use gbs_parser::write_binary::WriteBinary;
+
+const SIGNATURE : [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+const COLOR_GRAYSCALE : u8 = 0;
+const FILTER_NONE : u8 = 0;
+
+// Largest stored deflate block
+const BLOCK_SIZE : usize = 0xFFFF;
+
+// Write pixels, one byte per pixel row by row, as a width*height image
+pub fn write_gray<W: Write>(out: &mut W, width: u32, height: u32, pixels: &[u8])
+                            -> io::Result<()> {
+  assert_eq!(pixels.len(), (width * height) as usize);
+
+  out.write_all(&SIGNATURE)?;
+
+  let mut ihdr = Vec::new();
+  ihdr.write_u32_be(width)?;
+  ihdr.write_u32_be(height)?;
+  ihdr.write_all(&[8, COLOR_GRAYSCALE, 0, 0, 0])?;
+  write_chunk(out, b"IHDR", &ihdr)?;
+
+  // Each row starts with its filter type
+  let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+  for row in pixels.chunks(width as usize) {
+    raw.push(FILTER_NONE);
+    raw.extend_from_slice(row);
+  }
+  write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+
+  write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8])
+                         -> io::Result<()> {
+  out.write_u32_be(data.len() as u32)?;
+  out.write_all(kind)?;
+  out.write_all(data)?;
+  out.write_u32_be(crc32(&[kind, data]))
+}
+
+// zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  // Deflate, 32K window, no dictionary, with the header check bits
+  let mut z = vec![0x78, 0x01];
+
+  let mut blocks = data.chunks(BLOCK_SIZE).peekable();
+  if blocks.peek().is_none() {
+    z.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+  }
+  while let Some(block) = blocks.next() {
+    let last = blocks.peek().is_none();
+    let len = block.len() as u16;
+    z.push(last as u8);
+    z.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+    z.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+    z.extend_from_slice(block);
+  }
+
+  let adler = adler32(data);
+  z.extend_from_slice(&[(adler >> 24) as u8, (adler >> 16) as u8,
+                        (adler >> 8) as u8, adler as u8]);
+  z
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+  let mut crc = !0u32;
+  for &part in parts {
+    for &b in part {
+      crc ^= b as u32;
+      for _ in 0..8 {
+        crc = if crc & 1 > 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+      }
+    }
+  }
+  !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &w in data {
+    a = (a + w as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  b << 16 | a
+}
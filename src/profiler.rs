@@ -0,0 +1,194 @@
+// Cycle profiler for sound drivers.  Each call to PLAY is followed through
+// CALL, RST and RET, and the cycles of every instruction go to the routines on
+// the call stack:
+//
+// - exclusive cycles are those of the routine's own instructions,
+// - inclusive cycles also count the routines it called.
+//
+// The duration of each PLAY call is compared to the budget, the cycles between
+// two calls.  The call stacks can be written in the collapsed format read by
+// flamegraph.pl and similar tools.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use disassembler;
+use gb::bus::Bus;
+use gb::cpu::{Cpu, Lockup, R16};
+use symbols::Symbols;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Routine {
+  pub calls: u64,
+  pub inclusive: u64,
+  pub exclusive: u64,
+}
+
+pub struct Profiler {
+  // Entry addresses of the routines called, outermost first
+  path: Vec<u16>,
+  // SP right after each call, pointing at the return address
+  sps: Vec<u16>,
+  routines: HashMap<u16, Routine>,
+  // Exclusive cycles of each call stack
+  stacks: HashMap<Vec<u16>, u64>,
+  // Cycles between two PLAY calls
+  budget: u32,
+  plays: u64,
+  play_cycles: u64,
+  total: u64,
+  worst: u64,
+  // 0-based index of the worst PLAY call
+  worst_play: u64,
+}
+
+impl Profiler {
+  pub fn new(budget: u32) -> Self {
+    Profiler {
+      path: Vec::new(),
+      sps: Vec::new(),
+      routines: HashMap::new(),
+      stacks: HashMap::new(),
+      budget,
+      plays: 0,
+      play_cycles: 0,
+      total: 0,
+      worst: 0,
+      worst_play: 0,
+    }
+  }
+
+  // PLAY was just called: PC is at its start, and its return address is on
+  // the stack
+  pub fn enter<B: Bus>(&mut self, cpu: &Cpu<B>) {
+    self.path.clear();
+    self.sps.clear();
+    self.play_cycles = 0;
+    self.push(cpu.rr(R16::PC), cpu.rr(R16::SP));
+  }
+
+  // Run one instruction and account for its cycles
+  pub fn step<B: Bus>(&mut self, cpu: &mut Cpu<B>) -> Result<u8, Lockup> {
+    let pc = cpu.rr(R16::PC);
+    let sp = cpu.rr(R16::SP);
+    let inst = disassembler::decode_memory(|a| cpu.bus.peek(a), pc);
+
+    let cycles = cpu.step()?;
+    self.account(cycles as u64);
+
+    let new_sp = cpu.rr(R16::SP);
+    if inst.is_call() && new_sp == sp.wrapping_sub(2) {
+      self.push(cpu.rr(R16::PC), new_sp);
+    } else if inst.is_return() && new_sp == sp.wrapping_add(2) {
+      self.pop(new_sp);
+    }
+
+    Ok(cycles)
+  }
+
+  fn push(&mut self, addr: u16, sp: u16) {
+    self.path.push(addr);
+    self.sps.push(sp);
+    self.routines.entry(addr).or_default().calls += 1;
+  }
+
+  // Drop the frames returned from.  PLAY is done when none is left.
+  fn pop(&mut self, sp: u16) {
+    let was_running = !self.path.is_empty();
+    while self.sps.last().is_some_and(|&s| s < sp) {
+      self.path.pop();
+      self.sps.pop();
+    }
+
+    if was_running && self.path.is_empty() {
+      if self.play_cycles > self.worst {
+        self.worst = self.play_cycles;
+        self.worst_play = self.plays;
+      }
+      self.plays += 1;
+    }
+  }
+
+  fn account(&mut self, cycles: u64) {
+    let top = match self.path.last() {
+      Some(&top) => top,
+      None => return,
+    };
+
+    self.total += cycles;
+    self.play_cycles += cycles;
+    self.routines.entry(top).or_default().exclusive += cycles;
+    // Count recursive routines once
+    for (i, addr) in self.path.iter().enumerate() {
+      if !self.path[..i].contains(addr) {
+        self.routines.entry(*addr).or_default().inclusive += cycles;
+      }
+    }
+
+    match self.stacks.get_mut(self.path.as_slice()) {
+      Some(c) => *c += cycles,
+      None => { self.stacks.insert(self.path.clone(), cycles); },
+    }
+  }
+
+  pub fn routines(&self) -> &HashMap<u16, Routine> {
+    &self.routines
+  }
+
+  // Number of PLAY calls that returned
+  pub fn plays(&self) -> u64 {
+    self.plays
+  }
+
+  // Longest PLAY call in cycles, and its 0-based index
+  pub fn worst(&self) -> (u64, u64) {
+    (self.worst, self.worst_play)
+  }
+
+  pub fn write_report<W: Write>(&self, out: &mut W, symbols: &Symbols)
+                                -> io::Result<()> {
+    let average = self.total.checked_div(self.plays).unwrap_or(0);
+    writeln!(out, "PLAY calls:  {}", self.plays)?;
+    writeln!(out, "Average:     {} cycles", average)?;
+    writeln!(out, "Worst:       {} cycles (call {}), {:.1}% of the {}-cycle \
+                   budget{}",
+             self.worst, self.worst_play,
+             100.0 * self.worst as f64 / self.budget as f64, self.budget,
+             if self.worst > self.budget as u64 { " - OVER BUDGET" } else { "" })?;
+    writeln!(out)?;
+
+    let mut routines = self.routines.iter().collect::<Vec<_>>();
+    routines.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+
+    let percent = |c: u64| 100.0 * c as f64 / self.total.max(1) as f64;
+    writeln!(out, "{:<24} {:>8} {:>12} {:>6} {:>12} {:>6}",
+             "Routine", "Calls", "Inclusive", "%", "Exclusive", "%")?;
+    for (&addr, r) in routines {
+      writeln!(out, "{:<24} {:>8} {:>12} {:>6.1} {:>12} {:>6.1}",
+               symbols.describe(addr), r.calls, r.inclusive, percent(r.inclusive),
+               r.exclusive, percent(r.exclusive))?;
+    }
+    Ok(())
+  }
+
+  // One line per call stack: routines from the outermost, separated by
+  // semicolons, then the cycles spent in the innermost one
+  pub fn write_collapsed<W: Write>(&self, out: &mut W, symbols: &Symbols)
+                                   -> io::Result<()> {
+    let name = |addr: u16| symbols.name(addr)
+      .unwrap_or_else(|| format!("${:04X}", addr));
+
+    let mut stacks = self.stacks.iter()
+      .map(|(path, &cycles)| {
+        let names = path.iter().map(|&a| name(a)).collect::<Vec<_>>();
+        (names.join(";"), cycles)
+      })
+      .collect::<Vec<_>>();
+    stacks.sort();
+
+    for (path, cycles) in stacks {
+      writeln!(out, "{} {}", path, cycles)?;
+    }
+    Ok(())
+  }
+}
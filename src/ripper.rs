@@ -0,0 +1,131 @@
+// Build a GBS file out of a cartridge image, given where the sound driver
+// lives.
+//
+// The GBS data is the ROM from the load address onwards, so that pages stay
+// aligned as the spec requires: page 1 is still mapped at $4000.
+
+use gb::coverage::{self, Coverage};
+use gbs_parser::{Gbs, GbsError, Result};
+use player::GbsPlayer;
+use sink::Spec;
+
+// Size of the RST vector area at the start of the ROM
+const RST_AREA : usize = 0x40;
+const PAGE_SIZE : usize = 0x4000;
+
+pub struct Rip {
+  pub load_addr:  u16,
+  pub init_addr:  u16,
+  pub play_addr:  u16,
+  pub sp:         u16,
+  pub timer_mod:  u8,
+  pub timer_ctrl: u8,
+  pub n_songs:    u8,
+  pub first_song: u8,
+  pub title:      String,
+  pub author:     String,
+  pub copyright:  String,
+
+  // Copy the RST vectors of the ROM in front of the driver, since GBS players
+  // vector RSTs relative to the load address.  Moves the load address $40
+  // bytes down.
+  pub rst_patch:  bool,
+  // Keep only that many 16K pages.  This cuts blindly: whether the driver
+  // reads the pages dropped is not checked; see used_banks for that.
+  pub keep_banks: Option<usize>,
+  // Drop the padding at the end of the ROM
+  pub trim:       bool,
+}
+
+impl Rip {
+  pub fn new(load_addr: u16, init_addr: u16, play_addr: u16) -> Self {
+    Rip {
+      load_addr,
+      init_addr,
+      play_addr,
+      // Default of the GameBoy at startup
+      sp: 0xFFFE,
+      // Use v-blank
+      timer_mod: 0,
+      timer_ctrl: 0,
+      n_songs: 1,
+      first_song: 1,
+      // Unknown fields should be a single question mark
+      title: String::from("?"),
+      author: String::from("?"),
+      copyright: String::from("?"),
+      rst_patch: false,
+      keep_banks: None,
+      trim: false,
+    }
+  }
+
+  pub fn build(&self, rom: &[u8]) -> Result<Gbs> {
+    let mut load_addr = self.load_addr as usize;
+    let mut end = rom.len();
+    if let Some(banks) = self.keep_banks {
+      end = end.min(banks * PAGE_SIZE);
+    }
+    if load_addr < 0x400 || load_addr >= end {
+      return Err(GbsError::InvalidAddress);
+    }
+
+    if self.trim {
+      // Whatever byte the ROM is padded with
+      let pad = rom[end - 1];
+      while end > load_addr && rom[end - 1] == pad {
+        end -= 1;
+      }
+    }
+
+    let mut data = Vec::new();
+    if self.rst_patch {
+      load_addr -= RST_AREA;
+      data.extend_from_slice(&rom[..RST_AREA]);
+      data.extend_from_slice(&rom[load_addr + RST_AREA..end]);
+    } else {
+      data.extend_from_slice(&rom[load_addr..end]);
+    }
+
+    let gbs = Gbs {
+      version: 1,
+      n_songs: self.n_songs,
+      first_song: self.first_song,
+      load_addr: load_addr as u16,
+      init_addr: self.init_addr,
+      play_addr: self.play_addr,
+      sp: self.sp,
+      timer_mod: self.timer_mod,
+      timer_ctrl: self.timer_ctrl,
+      title: self.title.clone(),
+      author: self.author.clone(),
+      copyright: self.copyright.clone(),
+      rom: data,
+    };
+
+    gbs.validate()?;
+    Ok(gbs)
+  }
+}
+
+// Number of 16K pages of the ROM, counting from the start, up to the last one
+// touched while playing every song of gbs for length milliseconds.  Pages past
+// $7FFF are never mapped, so nothing is known about them: then all are kept.
+pub fn used_banks(gbs: &Gbs, length: u32) -> usize {
+  let mut player = GbsPlayer::new(gbs.clone(), Spec::new().sample_rate);
+  player.set_coverage(Coverage::new());
+  for track in 0..gbs.n_songs {
+    // Count what ran before a hang
+    let _ = player.start_track(track).and_then(|_| player.seek(length));
+  }
+
+  let start = gbs.load_addr as usize;
+  let end = start + gbs.rom.len();
+  let mask = player.coverage()
+    .map_or_else(Vec::new, |c| c.mask(gbs.load_addr, gbs.rom.len()));
+  if mask.contains(&coverage::UNMAPPED) {
+    return end.div_ceil(PAGE_SIZE);
+  }
+  let last = mask.iter().rposition(|&f| f != 0).map_or(start, |i| start + i);
+  last / PAGE_SIZE + 1
+}
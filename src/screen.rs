@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
 use glium::backend::Facade;
 use glium::index::PrimitiveType;
 use glium::{Surface, VertexBuffer, IndexBuffer, Program};
@@ -5,6 +8,10 @@ use glium::texture::{UncompressedFloatFormat, MipmapsOption};
 use glium::texture::texture2d::Texture2d;
 use glium::texture::pixel_buffer::PixelBuffer;
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+use glium::glutin::VirtualKeyCode;
+
+use gb::GB;
+use gb::state;
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -108,3 +115,42 @@ impl Screen {
                &uniforms, &Default::default()).unwrap();
   }
 }
+
+// Number keys select a save slot, F5 saves to it and F7 loads from it
+pub fn hotkey(gb: &mut GB, rom: &str, slot: &mut u8, key: VirtualKeyCode) {
+  let digits = [VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2,
+                VirtualKeyCode::Key3, VirtualKeyCode::Key4, VirtualKeyCode::Key5,
+                VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8,
+                VirtualKeyCode::Key9];
+
+  if let Some(n) = digits.iter().position(|&k| k == key) {
+    *slot = n as u8;
+    println!("Save slot {}", slot);
+    return;
+  }
+
+  let path = state::slot_path(rom, *slot);
+  match key {
+    VirtualKeyCode::F5 => {
+      let result = File::create(&path)
+        .map_err(state::StateError::from)
+        .and_then(|f| gb.save_state(&mut BufWriter::new(f)));
+      match result {
+        Ok(()) => println!("Saved state to {}", path.display()),
+        Err(e) => println!("Error saving state: {}", e),
+      }
+    }
+
+    VirtualKeyCode::F7 => {
+      let result = File::open(&path)
+        .map_err(state::StateError::from)
+        .and_then(|f| gb.load_state(&mut BufReader::new(f)));
+      match result {
+        Ok(()) => println!("Loaded state from {}", path.display()),
+        Err(e) => println!("Error loading state: {}", e),
+      }
+    }
+
+    _ => {},
+  }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use gbs_parser::write_binary::WriteBinary;
+use sink::{Encoder, Endian, SampleFormat, Sink, Spec, Tags};
+
+// Timestamp of the only AIFF-C version ever published
+const AIFC_VERSION : u32 = 0xA280_5140;
+
+// Audio IFF file.  Integer samples go in a plain AIFF, float samples need the
+// AIFF-C variant with the 'fl32' compression type.
+pub struct AiffSink<W: Write + Seek> {
+  out: W,
+  encoder: Encoder,
+  frames: u32,
+  tags: Option<Tags>,
+  // Offsets of the size fields we patch in finish
+  frames_offset: u64,
+  ssnd_offset: u64,
+}
+
+impl<W: Write + Seek> AiffSink<W> {
+  pub fn new(mut out: W, spec: Spec) -> io::Result<Self> {
+    let float = spec.format == SampleFormat::Float32;
+
+    out.write_all(b"FORM")?;
+    out.write_u32_be(0)?;
+    out.write_all(if float { b"AIFC" } else { b"AIFF" })?;
+
+    if float {
+      out.write_all(b"FVER")?;
+      out.write_u32_be(4)?;
+      out.write_u32_be(AIFC_VERSION)?;
+    }
+
+    let name = b"\x0C32-bit float\0";
+    out.write_all(b"COMM")?;
+    out.write_u32_be(if float { 18 + 4 + name.len() as u32 } else { 18 })?;
+    out.write_u16_be(spec.channels.count())?;
+    let frames_offset = out.stream_position()?;
+    out.write_u32_be(0)?;
+    out.write_u16_be(spec.format.bits())?;
+    write_extended(&mut out, spec.sample_rate)?;
+    if float {
+      out.write_all(b"fl32")?;
+      // Pascal string, padded to an even length
+      out.write_all(name)?;
+    }
+
+    out.write_all(b"SSND")?;
+    let ssnd_offset = out.stream_position()?;
+    out.write_u32_be(0)?;
+    // Offset and block size, unused
+    out.write_u32_be(0)?;
+    out.write_u32_be(0)?;
+
+    Ok(AiffSink {
+      out,
+      encoder: Encoder::new(spec, Endian::Big),
+      frames: 0,
+      tags: None,
+      frames_offset,
+      ssnd_offset,
+    })
+  }
+
+  fn data_len(&self) -> u32 {
+    self.frames * self.encoder.spec.block_align() as u32
+  }
+
+  // AIFF only has text chunks for the name, author and copyright.  Album and
+  // track number go into an ID3 chunk, which most players read as well.
+  fn write_tags(&mut self, tags: &Tags) -> io::Result<()> {
+    let chunks = [
+      (b"NAME", &tags.title),
+      (b"AUTH", &tags.artist),
+      (b"(c) ", &tags.copyright),
+    ];
+
+    for &(id, text) in chunks.iter().filter(|c| !c.1.is_empty()) {
+      let len = text.chars().count();
+      self.out.write_all(id)?;
+      self.out.write_u32_be(len as u32)?;
+      self.out.write_str(text, len + len % 2)?;
+    }
+
+    let id3 = id3_tag(tags)?;
+    self.out.write_all(b"ID3 ")?;
+    self.out.write_u32_be(id3.len() as u32)?;
+    self.out.write_all(&id3)?;
+    if id3.len() % 2 == 1 {
+      self.out.write_u8(0)?;
+    }
+    Ok(())
+  }
+}
+
+impl<W: Write + Seek> Sink for AiffSink<W> {
+  fn write(&mut self, left: f32, right: f32) -> io::Result<()> {
+    self.encoder.encode(&mut self.out, left, right)?;
+    self.frames += 1;
+    Ok(())
+  }
+
+  fn tag(&mut self, tags: &Tags) {
+    self.tags = Some(tags.clone());
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    let data_len = self.data_len();
+
+    // Chunks are word-aligned
+    if data_len % 2 == 1 {
+      self.out.write_u8(0)?;
+    }
+
+    // Metadata goes after the samples so it can be set at any time
+    if let Some(tags) = self.tags.take() {
+      self.write_tags(&tags)?;
+    }
+
+    let end = self.out.stream_position()?;
+    self.out.seek(SeekFrom::Start(4))?;
+    self.out.write_u32_be((end - 8) as u32)?;
+
+    self.out.seek(SeekFrom::Start(self.frames_offset))?;
+    self.out.write_u32_be(self.frames)?;
+
+    self.out.seek(SeekFrom::Start(self.ssnd_offset))?;
+    self.out.write_u32_be(data_len + 8)?;
+
+    self.out.seek(SeekFrom::Start(end))?;
+    self.out.flush()
+  }
+}
+
+// The sample rate is stored as an 80-bit IEEE 754 extended float
+fn write_extended<W: Write>(out: &mut W, v: u32) -> io::Result<()> {
+  if v == 0 {
+    return out.write_all(&[0; 10]);
+  }
+
+  // Normalize so that the explicit integer bit ends up in bit 63
+  let shift = v.leading_zeros();
+  let exponent = 16383 + 31 - shift as u16;
+  let mantissa = (v as u64) << (32 + shift);
+
+  out.write_u16_be(exponent)?;
+  out.write_u32_be((mantissa >> 32) as u32)?;
+  out.write_u32_be(mantissa as u32)
+}
+
+// ID3v2.3 tag with Latin-1 text frames
+fn id3_tag(tags: &Tags) -> io::Result<Vec<u8>> {
+  let frames = [
+    (b"TIT2", tags.title.clone()),
+    (b"TALB", tags.album.clone()),
+    (b"TPE1", tags.artist.clone()),
+    (b"TCOP", tags.copyright.clone()),
+    (b"TRCK", tags.track_string()),
+  ];
+
+  let mut body = Vec::new();
+  for &(id, ref text) in frames.iter().filter(|f| !f.1.is_empty()) {
+    let len = text.chars().count();
+    body.write_all(id)?;
+    // Encoding byte + text
+    body.write_u32_be(len as u32 + 1)?;
+    body.write_u16_be(0)?;
+    body.write_u8(0)?;
+    body.write_str(text, len)?;
+  }
+
+  // The tag size is a 28-bit "syncsafe" integer: 7 bits per byte
+  let size = body.len() as u32;
+  let mut tag = Vec::new();
+  tag.write_all(b"ID3")?;
+  tag.write_all(&[3, 0, 0])?;
+  tag.write_all(&[(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F,
+                  (size >> 7) as u8 & 0x7F, size as u8 & 0x7F])?;
+  tag.write_all(&body)?;
+  Ok(tag)
+}
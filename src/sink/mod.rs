@@ -0,0 +1,262 @@
+// Destinations for the stereo samples produced by the APU.
+//
+// The mixer hands out frames of two f32 in [-1.0,1.0] (or slightly beyond when
+// all channels are loud).  A sink downmixes them if needed, keeps them in range
+// and encodes them to the requested sample format.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gbs_parser::Gbs;
+use gbs_parser::write_binary::WriteBinary;
+
+mod wav;
+mod aiff;
+mod raw;
+
+pub use self::wav::WavSink;
+pub use self::aiff::AiffSink;
+pub use self::raw::RawSink;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SampleFormat {
+  Int16,
+  Int24,
+  Float32,
+}
+
+impl SampleFormat {
+  pub fn bits(self) -> u16 {
+    match self {
+      SampleFormat::Int16 => 16,
+      SampleFormat::Int24 => 24,
+      SampleFormat::Float32 => 32,
+    }
+  }
+
+  pub fn bytes(self) -> u16 {
+    self.bits() / 8
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Channels {
+  Mono,
+  Stereo,
+}
+
+impl Channels {
+  pub fn count(self) -> u16 {
+    match self {
+      Channels::Mono => 1,
+      Channels::Stereo => 2,
+    }
+  }
+}
+
+// What to do with samples outside [-1.0,1.0]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Clipping {
+  // Clamp each sample
+  Clip,
+  // Lower the gain as soon as a peak would clip, and slowly bring it back
+  Limit,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Spec {
+  pub channels: Channels,
+  pub sample_rate: u32,
+  pub format: SampleFormat,
+  pub clipping: Clipping,
+}
+
+impl Spec {
+  // What the gbs player always produced: CD-quality stereo
+  pub fn new() -> Self {
+    Spec {
+      channels: Channels::Stereo,
+      sample_rate: 44100,
+      format: SampleFormat::Int16,
+      clipping: Clipping::Clip,
+    }
+  }
+
+  pub fn block_align(&self) -> u16 {
+    self.channels.count() * self.format.bytes()
+  }
+}
+
+impl Default for Spec {
+  fn default() -> Self {
+    Spec::new()
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Container {
+  Wav,
+  Aiff,
+  Raw,
+}
+
+impl Container {
+  // Guess the container from a file extension
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+    let ext = path.as_ref().extension()
+      .and_then(|e| e.to_str())
+      .map(|e| e.to_lowercase());
+
+    match ext.as_deref() {
+      Some("wav") => Some(Container::Wav),
+      Some("aif") | Some("aiff") | Some("aifc") => Some(Container::Aiff),
+      Some("raw") | Some("pcm") => Some(Container::Raw),
+      _ => None,
+    }
+  }
+}
+
+// Descriptive metadata embedded in the rendered file.  Empty strings are left
+// out.
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+  pub title: String,
+  pub album: String,
+  pub artist: String,
+  pub copyright: String,
+  // 1-based
+  pub track: u8,
+  pub track_count: u8,
+}
+
+impl Tags {
+  // Tags for a track of a GBS file.  The file title is the album, and GBS has
+  // no per-track titles.
+  pub fn from_gbs(gbs: &Gbs, track: u8) -> Self {
+    Tags {
+      title: format!("Track {}", track + 1),
+      album: gbs.title.clone(),
+      artist: gbs.author.clone(),
+      copyright: gbs.copyright.clone(),
+      track: track + 1,
+      track_count: gbs.n_songs,
+    }
+  }
+
+  // Track number as "n/total"
+  pub fn track_string(&self) -> String {
+    if self.track_count > 0 {
+      format!("{}/{}", self.track, self.track_count)
+    } else {
+      format!("{}", self.track)
+    }
+  }
+}
+
+pub trait Sink {
+  // Write one stereo frame
+  fn write(&mut self, left: f32, right: f32) -> io::Result<()>;
+
+  // Set the metadata written by finish.  Sinks without a header ignore it.
+  fn tag(&mut self, tags: &Tags);
+
+  // Flush everything and fix up the headers.  Nothing should be written after.
+  fn finish(&mut self) -> io::Result<()>;
+}
+
+// Create a file sink of the given container
+pub fn create<P: AsRef<Path>>(path: P, container: Container, spec: Spec)
+                              -> io::Result<Box<dyn Sink>> {
+  let out = BufWriter::new(File::create(path)?);
+  Ok(match container {
+    Container::Wav => Box::new(WavSink::new(out, spec)?),
+    Container::Aiff => Box::new(AiffSink::new(out, spec)?),
+    Container::Raw => Box::new(RawSink::new(out, spec)),
+  })
+}
+
+#[derive(Copy, Clone)]
+enum Endian {
+  Little,
+  Big,
+}
+
+// Shared by all sinks: downmix, keep samples in range and encode them.
+struct Encoder {
+  spec: Spec,
+  endian: Endian,
+  gain: f32,
+  release: f32,
+}
+
+impl Encoder {
+  fn new(spec: Spec, endian: Endian) -> Self {
+    // The limiter recovers from a peak in about 100ms
+    let release = 1.0 - (-1.0 / (0.1 * spec.sample_rate as f32)).exp();
+
+    Encoder {
+      spec,
+      endian,
+      gain: 1.0,
+      release,
+    }
+  }
+
+  fn encode<W: Write>(&mut self, out: &mut W, left: f32, right: f32)
+                      -> io::Result<()> {
+    let (left, right) = self.limit(left, right);
+
+    match self.spec.channels {
+      Channels::Mono => self.encode_sample(out, (left + right) / 2.0),
+      Channels::Stereo => {
+        self.encode_sample(out, left)?;
+        self.encode_sample(out, right)
+      }
+    }
+  }
+
+  fn limit(&mut self, left: f32, right: f32) -> (f32, f32) {
+    if self.spec.clipping == Clipping::Limit {
+      let peak = left.abs().max(right.abs());
+      if peak * self.gain > 1.0 {
+        self.gain = 1.0 / peak;
+      } else {
+        self.gain += (1.0 - self.gain) * self.release;
+      }
+      (left * self.gain, right * self.gain)
+    } else {
+      (left, right)
+    }
+  }
+
+  fn encode_sample<W: Write>(&self, out: &mut W, s: f32) -> io::Result<()> {
+    let s = s.clamp(-1.0, 1.0);
+
+    match self.spec.format {
+      SampleFormat::Int16 => {
+        let v = (s * i16::MAX as f32).round() as i16 as u16;
+        match self.endian {
+          Endian::Little => out.write_u16_le(v),
+          Endian::Big => out.write_u16_be(v),
+        }
+      }
+
+      SampleFormat::Int24 => {
+        let v = (s * 8_388_607.0).round() as i32 as u32;
+        match self.endian {
+          Endian::Little => out.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8]),
+          Endian::Big => out.write_all(&[(v >> 16) as u8, (v >> 8) as u8, v as u8]),
+        }
+      }
+
+      SampleFormat::Float32 => {
+        let v = s.to_bits();
+        match self.endian {
+          Endian::Little => out.write_u32_le(v),
+          Endian::Big => out.write_u32_be(v),
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,31 @@
+use std::io::{self, Write};
+
+use sink::{Encoder, Endian, Sink, Spec, Tags};
+
+// Headerless little-endian PCM.  Whoever reads it back must know the spec.
+pub struct RawSink<W: Write> {
+  out: W,
+  encoder: Encoder,
+}
+
+impl<W: Write> RawSink<W> {
+  pub fn new(out: W, spec: Spec) -> Self {
+    RawSink {
+      out,
+      encoder: Encoder::new(spec, Endian::Little),
+    }
+  }
+}
+
+impl<W: Write> Sink for RawSink<W> {
+  fn write(&mut self, left: f32, right: f32) -> io::Result<()> {
+    self.encoder.encode(&mut self.out, left, right)
+  }
+
+  // Nowhere to put them
+  fn tag(&mut self, _tags: &Tags) {}
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+}
@@ -0,0 +1,136 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use gbs_parser::write_binary::WriteBinary;
+use sink::{Encoder, Endian, SampleFormat, Sink, Spec, Tags};
+
+const FORMAT_PCM : u16 = 1;
+const FORMAT_IEEE_FLOAT : u16 = 3;
+
+// RIFF WAVE file.  Integer samples use the plain PCM format, float samples the
+// IEEE float format.
+pub struct WavSink<W: Write + Seek> {
+  out: W,
+  encoder: Encoder,
+  frames: u32,
+  tags: Option<Tags>,
+  // Offsets of the size fields we patch in finish
+  fact_offset: Option<u64>,
+  data_offset: u64,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+  pub fn new(mut out: W, spec: Spec) -> io::Result<Self> {
+    let float = spec.format == SampleFormat::Float32;
+    let channels = spec.channels.count();
+    let block_align = spec.block_align();
+
+    out.write_all(b"RIFF")?;
+    out.write_u32_le(0)?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_u32_le(if float { 18 } else { 16 })?;
+    out.write_u16_le(if float { FORMAT_IEEE_FLOAT } else { FORMAT_PCM })?;
+    out.write_u16_le(channels)?;
+    out.write_u32_le(spec.sample_rate)?;
+    out.write_u32_le(spec.sample_rate * block_align as u32)?;
+    out.write_u16_le(block_align)?;
+    out.write_u16_le(spec.format.bits())?;
+
+    // Non-PCM formats need an (empty) extension and a fact chunk
+    let fact_offset = if float {
+      out.write_u16_le(0)?;
+      out.write_all(b"fact")?;
+      out.write_u32_le(4)?;
+      let offset = out.stream_position()?;
+      out.write_u32_le(0)?;
+      Some(offset)
+    } else {
+      None
+    };
+
+    out.write_all(b"data")?;
+    let data_offset = out.stream_position()?;
+    out.write_u32_le(0)?;
+
+    Ok(WavSink {
+      out,
+      encoder: Encoder::new(spec, Endian::Little),
+      frames: 0,
+      tags: None,
+      fact_offset,
+      data_offset,
+    })
+  }
+
+  fn data_len(&self) -> u32 {
+    self.frames * self.encoder.spec.block_align() as u32
+  }
+
+  // RIFF LIST/INFO chunk, understood by most players and taggers
+  fn write_info(&mut self, tags: &Tags) -> io::Result<()> {
+    let fields = [
+      (b"INAM", tags.title.clone()),
+      (b"IPRD", tags.album.clone()),
+      (b"IART", tags.artist.clone()),
+      (b"ICOP", tags.copyright.clone()),
+      (b"ITRK", tags.track_string()),
+      (b"ISFT", String::from("gbs")),
+    ];
+
+    let mut info = Vec::new();
+    info.write_all(b"INFO")?;
+    for &(id, ref text) in fields.iter().filter(|f| !f.1.is_empty()) {
+      // Null-terminated, and padded to an even length
+      let len = text.chars().count() + 1;
+      info.write_all(id)?;
+      info.write_u32_le(len as u32)?;
+      info.write_str(text, len + len % 2)?;
+    }
+
+    self.out.write_all(b"LIST")?;
+    self.out.write_u32_le(info.len() as u32)?;
+    self.out.write_all(&info)
+  }
+}
+
+impl<W: Write + Seek> Sink for WavSink<W> {
+  fn write(&mut self, left: f32, right: f32) -> io::Result<()> {
+    self.encoder.encode(&mut self.out, left, right)?;
+    self.frames += 1;
+    Ok(())
+  }
+
+  fn tag(&mut self, tags: &Tags) {
+    self.tags = Some(tags.clone());
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    let data_len = self.data_len();
+
+    // Chunks are word-aligned
+    if data_len % 2 == 1 {
+      self.out.write_u8(0)?;
+    }
+
+    // Metadata goes after the samples so it can be set at any time
+    if let Some(tags) = self.tags.take() {
+      self.write_info(&tags)?;
+    }
+
+    let end = self.out.stream_position()?;
+    self.out.seek(SeekFrom::Start(4))?;
+    self.out.write_u32_le((end - 8) as u32)?;
+
+    if let Some(offset) = self.fact_offset {
+      self.out.seek(SeekFrom::Start(offset))?;
+      self.out.write_u32_le(self.frames)?;
+    }
+
+    self.out.seek(SeekFrom::Start(self.data_offset))?;
+    self.out.write_u32_le(data_len)?;
+
+    self.out.seek(SeekFrom::Start(end))?;
+    self.out.flush()
+  }
+}
@@ -0,0 +1,178 @@
+// Symbol files, as written by rgblink and read by no$gmb and BGB:
+//
+//   ; comment
+//   00:0150 Start
+//   01:4000 SongTable
+//
+// Each line gives the bank and address of a label, in hexadecimal.  They are
+// used to name addresses in traces and disassemblies, as the closest label
+// before the address plus an offset.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::result;
+
+pub type Result<T> = result::Result<T, SymbolError>;
+
+#[derive(Debug)]
+pub enum SymbolError {
+  Io(io::Error),
+  // Line number (1-based) of a malformed entry
+  InvalidEntry(usize),
+}
+
+impl From<io::Error> for SymbolError {
+  fn from(err: io::Error) -> SymbolError {
+    SymbolError::Io(err)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbols {
+  // Keyed by bank then address
+  labels: BTreeMap<(u16, u16), String>,
+  // ROM bank assumed to be mapped at $4000-$7FFF
+  pub rom_bank: u16,
+}
+
+impl Default for Symbols {
+  fn default() -> Self {
+    Symbols::new()
+  }
+}
+
+impl Symbols {
+  pub fn new() -> Self {
+    Symbols {
+      labels: BTreeMap::new(),
+      rom_bank: 1,
+    }
+  }
+
+  pub fn parse<R: BufRead>(input: R) -> self::Result<Self> {
+    let mut symbols = Symbols::new();
+
+    for (n, line) in input.lines().enumerate() {
+      let line = line?;
+      let line = match line.find(';') {
+        Some(i) => &line[..i],
+        None => &line,
+      };
+
+      let mut fields = line.split_whitespace();
+      let (location, name) = match (fields.next(), fields.next()) {
+        (None, _) => continue,
+        (Some(location), Some(name)) => (location, name),
+        (Some(_), None) => return Err(SymbolError::InvalidEntry(n + 1)),
+      };
+
+      let mut parts = location.splitn(2, ':');
+      let bank = parts.next().and_then(|b| u16::from_str_radix(b, 16).ok());
+      let addr = parts.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+      match (bank, addr) {
+        (Some(bank), Some(addr)) => symbols.insert(bank, addr, name),
+        _ => return Err(SymbolError::InvalidEntry(n + 1)),
+      }
+    }
+
+    Ok(symbols)
+  }
+
+  // Keep the first label given for an address
+  pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+    self.labels.entry((bank, addr)).or_insert_with(|| name.to_string());
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.labels.is_empty()
+  }
+
+  // All labels, as (bank, address, name)
+  pub fn iter(&self) -> impl Iterator<Item=(u16, u16, &str)> {
+    self.labels.iter().map(|(&(bank, addr), name)| (bank, addr, name.as_str()))
+  }
+
+  // Labels in the banks currently mapped, as (address, name)
+  pub fn mapped(&self) -> impl Iterator<Item=(u16, &str)> {
+    self.iter()
+      .filter(move |&(bank, addr, _)| bank == self.bank(addr))
+      .map(|(_, addr, name)| (addr, name))
+  }
+
+  // Address of a label, if it is mapped
+  pub fn addr(&self, name: &str) -> Option<u16> {
+    self.mapped().find(|&(_, n)| n == name).map(|(addr, _)| addr)
+  }
+
+  // Closest label at or before addr in the same memory area, and the offset
+  // from it
+  pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+    let bank = self.bank(addr);
+    let start = area_start(addr);
+    self.labels.range((bank, start)..=(bank, addr)).next_back()
+      .map(|(&(_, a), name)| (name.as_str(), addr - a))
+  }
+
+  // "Label" or "Label+$xx", if there is a label for addr
+  pub fn name(&self, addr: u16) -> Option<String> {
+    self.lookup(addr).map(|(name, offset)| match offset {
+      0 => name.to_string(),
+      _ => format!("{}+${:x}", name, offset),
+    })
+  }
+
+  // The address named by a label when there is one, in hexadecimal otherwise
+  pub fn describe(&self, addr: u16) -> String {
+    match self.name(addr) {
+      Some(name) => format!("{} (${:04X})", name, addr),
+      None => format!("${:04X}", addr),
+    }
+  }
+
+  // Bank of a label at addr, as rgblink numbers them
+  fn bank(&self, addr: u16) -> u16 {
+    match addr {
+      0x4000..=0x7FFF => self.rom_bank,
+      0xD000..=0xDFFF => 1,
+      _ => 0,
+    }
+  }
+}
+
+// Labels do not name addresses outside of their memory area
+fn area_start(addr: u16) -> u16 {
+  match addr {
+    0x0000..=0x3FFF => 0x0000,
+    0x4000..=0x7FFF => 0x4000,
+    0x8000..=0x9FFF => 0x8000,
+    0xA000..=0xBFFF => 0xA000,
+    0xC000..=0xCFFF => 0xC000,
+    0xD000..=0xDFFF => 0xD000,
+    0xE000..=0xFDFF => 0xE000,
+    0xFE00..=0xFEFF => 0xFE00,
+    0xFF00..=0xFF7F => 0xFF00,
+    0xFF80..=0xFFFE => 0xFF80,
+    _ => 0xFFFF,
+  }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> self::Result<Symbols> {
+  Symbols::parse(BufReader::new(File::open(path)?))
+}
+
+// Where rgblink puts the symbols of a ROM
+pub fn sym_path<P: AsRef<Path>>(rom: P) -> PathBuf {
+  rom.as_ref().with_extension("sym")
+}
+
+// The symbols next to a ROM, or none if there is no symbol file
+pub fn load_beside<P: AsRef<Path>>(rom: P) -> self::Result<Symbols> {
+  let path = sym_path(rom);
+  if path.exists() {
+    load(path)
+  } else {
+    Ok(Symbols::new())
+  }
+}
@@ -0,0 +1,189 @@
+// Headless runs of test ROMs.  They tell their result in one of these ways:
+//
+// - blargg's ROMs print text on the serial port, with "Passed" or "Failed"
+//   at the end.  Some also write it at $A004, after the $DE $B0 $61 signature
+//   at $A001, and a status at $A000: $80 while running, 0 when passed.
+// - Mooneye's ROMs run LD B,B when done, with the Fibonacci numbers 3, 5, 8,
+//   13, 21 and 34 in B, C, D, E, H and L when passed, or $42 in all of them
+//   when failed.
+//
+// A ROM that locks up the CPU with an illegal opcode fails.
+//
+// ROMs are mapped without banking, so only 32K ROMs can run.  Those of
+// cartridges with a bank controller are turned down too, unless the header
+// says the ROM is only 32K: blargg's single tests are like this, and never
+// switch banks.
+
+use std::fmt;
+
+use gb::GB;
+use gb::bus::Bus;
+use gb::cpu::{Cpu, R8, R16};
+use gb::model::Model;
+use player::VBLANK_PERIOD;
+
+const LD_B_B : u8 = 0x40;
+const FIBONACCI : [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL : u8 = 0x42;
+const BLARGG_SIGNATURE : [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING : u8 = 0x80;
+
+const MAPPED_SIZE : usize = 0x8000;
+// Cartridge header
+const CARTRIDGE_TYPE : usize = 0x147;
+const ROM_SIZE : usize = 0x148;
+// Cartridge types without a bank controller: ROM, ROM+RAM, ROM+RAM+BATTERY
+const NO_MBC : [u8; 3] = [0x00, 0x08, 0x09];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Outcome {
+  Passed,
+  Failed,
+  Timeout,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+  pub outcome: Outcome,
+  // Cycles run until the result was known
+  pub cycles: u64,
+  // What the ROM printed, or its registers for Mooneye's
+  pub output: String,
+}
+
+// Why a ROM cannot run
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Unsupported {
+  // Larger than what is mapped without banking.  Holds the size.
+  TooLarge(usize),
+  // The header asks for bank switching.  Holds the cartridge type.
+  Banked(u8),
+}
+
+impl fmt::Display for Unsupported {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Unsupported::TooLarge(n) =>
+        write!(f, "ROM is {} bytes, banking is not supported", n),
+      Unsupported::Banked(t) =>
+        write!(f, "cartridge type ${:02X} with banks is not supported", t),
+    }
+  }
+}
+
+// Whether rom can run without banking
+fn check(rom: &[u8]) -> Result<(), Unsupported> {
+  if rom.len() > MAPPED_SIZE {
+    return Err(Unsupported::TooLarge(rom.len()));
+  }
+  let kind = rom.get(CARTRIDGE_TYPE).cloned().unwrap_or(0);
+  let banks = rom.get(ROM_SIZE).cloned().unwrap_or(0);
+  if !NO_MBC.contains(&kind) && banks > 0 {
+    return Err(Unsupported::Banked(kind));
+  }
+  Ok(())
+}
+
+// Run rom on model for at most timeout cycles
+pub fn run(rom: &[u8], model: Model, timeout: u64)
+           -> Result<Report, Unsupported> {
+  check(rom)?;
+
+  let mut gb = GB::new();
+  gb.load_rom(rom, 0);
+  gb.reset_to(model);
+
+  let mut cycles = 0;
+  let mut next_check = VBLANK_PERIOD as u64;
+  let mut serial_len = 0;
+  let mut last_serial = 0;
+
+  while cycles < timeout {
+    let pc = gb.cpu.rr(R16::PC);
+    if gb.cpu.bus.peek(pc) == LD_B_B {
+      if let Some(outcome) = mooneye_result(&gb.cpu) {
+        return Ok(Report { outcome, cycles, output: registers(&gb.cpu) });
+      }
+    }
+
+    match gb.cpu.step() {
+      Ok(c) => cycles += c as u64,
+      Err(lockup) => {
+        let serial = text(gb.cpu.bus.serial_output());
+        let output = format!("{}\nCPU locked up: {}", serial, lockup);
+        return Ok(Report { outcome: Outcome::Failed, cycles,
+                           output: output.trim().to_string() });
+      }
+    }
+
+    // Let the ROM finish printing the details that follow the result
+    let serial = gb.cpu.bus.serial_output();
+    if serial.len() != serial_len {
+      serial_len = serial.len();
+      last_serial = cycles;
+    }
+    if cycles - last_serial >= VBLANK_PERIOD as u64 {
+      if let Some(outcome) = text_result(serial) {
+        return Ok(Report { outcome, cycles, output: text(serial) });
+      }
+    }
+
+    if cycles >= next_check {
+      next_check += VBLANK_PERIOD as u64;
+      if let Some(report) = memory_result(&gb, cycles) {
+        return Ok(report);
+      }
+    }
+  }
+
+  let serial = gb.cpu.bus.serial_output();
+  Ok(Report { outcome: Outcome::Timeout, cycles, output: text(serial) })
+}
+
+fn mooneye_result(cpu: &Cpu) -> Option<Outcome> {
+  let regs = [cpu.r(R8::B), cpu.r(R8::C), cpu.r(R8::D),
+              cpu.r(R8::E), cpu.r(R8::H), cpu.r(R8::L)];
+  if regs == FIBONACCI {
+    Some(Outcome::Passed)
+  } else if regs.iter().all(|&r| r == MOONEYE_FAIL) {
+    Some(Outcome::Failed)
+  } else {
+    None
+  }
+}
+
+fn registers(cpu: &Cpu) -> String {
+  format!("B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+          cpu.r(R8::B), cpu.r(R8::C), cpu.r(R8::D),
+          cpu.r(R8::E), cpu.r(R8::H), cpu.r(R8::L))
+}
+
+fn text_result(text: &[u8]) -> Option<Outcome> {
+  let text = String::from_utf8_lossy(text);
+  if text.contains("Failed") {
+    Some(Outcome::Failed)
+  } else if text.contains("Passed") {
+    Some(Outcome::Passed)
+  } else {
+    None
+  }
+}
+
+fn memory_result(gb: &GB, cycles: u64) -> Option<Report> {
+  let hw = &gb.cpu.bus;
+  let signature = [hw.peek(0xA001), hw.peek(0xA002), hw.peek(0xA003)];
+  let status = hw.peek(0xA000);
+  if signature != BLARGG_SIGNATURE || status == BLARGG_RUNNING {
+    return None;
+  }
+
+  let output = (0xA004..0xC000).map(|a| hw.peek(a))
+    .take_while(|&b| b != 0)
+    .collect::<Vec<_>>();
+  let outcome = if status == 0 { Outcome::Passed } else { Outcome::Failed };
+  Some(Report { outcome, cycles, output: text(&output) })
+}
+
+fn text(bytes: &[u8]) -> String {
+  String::from_utf8_lossy(bytes).trim().to_string()
+}
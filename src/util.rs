@@ -0,0 +1,14 @@
+// Parsing of numbers given on the command line: decimal, or hexadecimal with a
+// 0x or $ prefix.
+
+pub fn parse_u16(s: &str) -> Option<u16> {
+  if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+    u16::from_str_radix(hex, 16).ok()
+  } else {
+    s.parse().ok()
+  }
+}
+
+pub fn parse_u8(s: &str) -> Option<u8> {
+  parse_u16(s).and_then(|n| if n < 0x100 { Some(n as u8) } else { None })
+}
@@ -0,0 +1,168 @@
+// Conformance of the CPU against the SM83 single-step test vectors:
+//
+//   https://github.com/SingleStepTests/sm83
+//
+// Each JSON file holds the vectors of one opcode ("00.json" ... "cb ff.json"):
+// the registers and memory before and after running a single instruction, and
+// the memory accesses of each M-cycle.  Only the number of M-cycles is checked,
+// not the accesses themselves.  Point SM83_TESTS to the directory holding the
+// files; the test is skipped when it is not set.
+//
+// Every mismatching register, flag, memory byte or cycle count is reported,
+// for the first few failing vectors of each opcode.
+
+extern crate gbs;
+extern crate serde_json;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+use serde_json::Value;
+
+use gbs::gb::bus::FlatBus;
+use gbs::gb::cpu::{Cpu, R8, R16};
+
+// Failing vectors reported in full for each opcode
+const REPORTED_PER_FILE : usize = 3;
+
+#[test]
+fn sm83_single_step() {
+  let dir = match env::var("SM83_TESTS") {
+    Ok(dir) => dir,
+    Err(_) => {
+      println!("SM83_TESTS is not set, skipping");
+      return;
+    }
+  };
+
+  let mut files = fs::read_dir(&dir).expect("Cannot read SM83_TESTS")
+    .filter_map(|e| e.ok().map(|e| e.path()))
+    .filter(|p| p.extension().is_some_and(|e| e == "json"))
+    .collect::<Vec<_>>();
+  files.sort();
+  assert!(!files.is_empty(), "No test vectors in {}", dir);
+
+  let mut total = 0;
+  let mut failed = 0;
+  let mut failed_opcodes = Vec::new();
+
+  for path in &files {
+    let vectors = load(path);
+    let mut failures = Vec::new();
+
+    for v in &vectors {
+      let errors = run(v);
+      if !errors.is_empty() {
+        failures.push((v["name"].as_str().unwrap_or("?").to_string(), errors));
+      }
+    }
+
+    total += vectors.len();
+    if failures.is_empty() {
+      continue;
+    }
+
+    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+    println!("{}: {} of {} vectors failed", name, failures.len(),
+             vectors.len());
+    for (vector, errors) in failures.iter().take(REPORTED_PER_FILE) {
+      println!("  {}: {}", vector, errors.join(", "));
+    }
+    failed += failures.len();
+    failed_opcodes.push(name);
+  }
+
+  println!("{} of {} vectors failed", failed, total);
+  assert!(failed_opcodes.is_empty(), "Failing opcodes: {}",
+          failed_opcodes.join(" "));
+}
+
+// Run one vector, and describe how the final state differs
+fn run(v: &Value) -> Vec<String> {
+  let mut cpu = Cpu::new(FlatBus::new());
+  let initial = &v["initial"];
+
+  for &(name, r) in &R8S {
+    cpu.r_set(r, number(&initial[name]) as u8);
+  }
+  cpu.rr_set(R16::SP, number(&initial["sp"]));
+  cpu.rr_set(R16::PC, number(&initial["pc"]));
+  cpu.ime = number(&initial["ime"]) as u8;
+  for entry in array(&initial["ram"]) {
+    cpu.bus.ram[number(&entry[0]) as usize] = number(&entry[1]) as u8;
+  }
+
+  let cycles = match cpu.step() {
+    Ok(cycles) => cycles as usize,
+    Err(lockup) => return vec![format!("locked up by {}", lockup)],
+  };
+
+  let mut errors = Vec::new();
+  let expected = &v["final"];
+
+  for &(name, r) in &R8S {
+    let want = number(&expected[name]) as u8;
+    let got = cpu.r(r);
+    if name == "f" {
+      for (bit, flag) in ["c", "h", "n", "z"].iter().enumerate() {
+        let mask = 0x10 << bit;
+        if got & mask != want & mask {
+          errors.push(format!("flag {} = {}, expected {}", flag,
+                              (got & mask > 0) as u8, (want & mask > 0) as u8));
+        }
+      }
+    } else if got != want {
+      errors.push(format!("{} = {:02x}, expected {:02x}", name, got, want));
+    }
+  }
+  for &(name, rr) in &[("sp", R16::SP), ("pc", R16::PC)] {
+    let want = number(&expected[name]);
+    let got = cpu.rr(rr);
+    if got != want {
+      errors.push(format!("{} = {:04x}, expected {:04x}", name, got, want));
+    }
+  }
+  let ime = number(&expected["ime"]) as u8;
+  if cpu.ime != ime {
+    errors.push(format!("ime = {}, expected {}", cpu.ime, ime));
+  }
+
+  for entry in array(&expected["ram"]) {
+    let addr = number(&entry[0]);
+    let want = number(&entry[1]) as u8;
+    let got = cpu.bus.ram[addr as usize];
+    if got != want {
+      errors.push(format!("[{:04x}] = {:02x}, expected {:02x}", addr, got, want));
+    }
+  }
+
+  // One entry per M-cycle
+  let want = array(&v["cycles"]).len() * 4;
+  if cycles != want {
+    errors.push(format!("{} cycles, expected {}", cycles, want));
+  }
+
+  errors
+}
+
+const R8S : [(&str, R8); 8] = [
+  ("a", R8::A), ("b", R8::B), ("c", R8::C), ("d", R8::D),
+  ("e", R8::E), ("f", R8::F), ("h", R8::H), ("l", R8::L),
+];
+
+fn load(path: &Path) -> Vec<Value> {
+  let file = File::open(path)
+    .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+  serde_json::from_reader(BufReader::new(file))
+    .unwrap_or_else(|e| panic!("Malformed JSON in {}: {}", path.display(), e))
+}
+
+fn number(v: &Value) -> u16 {
+  v.as_u64().unwrap_or(0) as u16
+}
+
+fn array(v: &Value) -> &[Value] {
+  v.as_array().map_or(&[], |a| &a[..])
+}